@@ -0,0 +1,23 @@
+#![cfg_attr(not(feature = "std"), no_std, no_main)]
+
+/// PSP34 collection that holds the collateral nfts in the e2e tests of the loan contract
+#[openbrush::implementation(PSP34, PSP34Mintable)]
+#[openbrush::contract]
+pub mod collateral_nft {
+    use openbrush::traits::Storage;
+
+    #[ink(storage)]
+    #[derive(Default, Storage)]
+    pub struct CollateralNft {
+        #[storage_field]
+        psp34: psp34::Data,
+    }
+
+    impl CollateralNft {
+        /// Constructor that creates an empty collection, anyone can mint into it
+        #[ink(constructor)]
+        pub fn new() -> Self {
+            Self::default()
+        }
+    }
+}
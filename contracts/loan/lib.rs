@@ -1,41 +1,651 @@
 #![cfg_attr(not(feature = "std"), no_std, no_main)]
 
-#[derive(scale::Encode)]
-enum RuntimeCall {
-    #[codec(index = 7)]
-    CommunityLoanPool(CommunityLoanPoolCall),
+use ink::primitives::AccountId;
+use scale::{Compact, Encode};
+use xcavate_lending_protocol::traits::loan::RuntimeCallIndices;
+
+/// Call of a pallet, encoded with the indices of the current runtime
+struct RuntimeCall<Call> {
+    indices: RuntimeCallIndices,
+    call: Call,
 }
 
-#[derive(scale::Encode)]
+// The variants are named after the calls of the community-loan-pool pallet
+#[allow(clippy::enum_variant_names)]
 enum CommunityLoanPoolCall {
-    #[codec(index = 3)]
     DeleteLoan { loan_id: u32 },
 
-    #[codec(index = 4)]
     UpdateLoan { loan_id: u32, amount: u128 },
+
+    LiquidateLoan { loan_id: u32, shortfall: u128 },
+}
+
+// The variants are named after the calls of the assets pallet
+enum AssetsCall {
+    Transfer {
+        id: u32,
+        target: AccountId,
+        amount: u128,
+    },
+
+    TransferApproved {
+        id: u32,
+        owner: AccountId,
+        destination: AccountId,
+        amount: u128,
+    },
+}
+
+impl scale::Encode for RuntimeCall<CommunityLoanPoolCall> {
+    fn encode_to<T: scale::Output + ?Sized>(&self, dest: &mut T) {
+        dest.push_byte(self.indices.community_loan_pool);
+        match self.call {
+            CommunityLoanPoolCall::DeleteLoan { loan_id } => {
+                dest.push_byte(self.indices.delete_loan);
+                loan_id.encode_to(dest);
+            }
+            CommunityLoanPoolCall::UpdateLoan { loan_id, amount } => {
+                dest.push_byte(self.indices.update_loan);
+                loan_id.encode_to(dest);
+                amount.encode_to(dest);
+            }
+            CommunityLoanPoolCall::LiquidateLoan { loan_id, shortfall } => {
+                dest.push_byte(self.indices.liquidate_loan);
+                loan_id.encode_to(dest);
+                shortfall.encode_to(dest);
+            }
+        }
+    }
+}
+
+/// Encodes an account as the `MultiAddress::Id` the assets pallet looks up accounts with
+fn encode_address<T: scale::Output + ?Sized>(account: &AccountId, dest: &mut T) {
+    dest.push_byte(0);
+    account.encode_to(dest);
+}
+
+impl scale::Encode for RuntimeCall<AssetsCall> {
+    fn encode_to<T: scale::Output + ?Sized>(&self, dest: &mut T) {
+        dest.push_byte(self.indices.assets);
+        // The asset ids and amounts are compact encoded parameters of the pallet
+        match &self.call {
+            AssetsCall::Transfer { id, target, amount } => {
+                dest.push_byte(self.indices.asset_transfer);
+                Compact(*id).encode_to(dest);
+                encode_address(target, dest);
+                Compact(*amount).encode_to(dest);
+            }
+            AssetsCall::TransferApproved {
+                id,
+                owner,
+                destination,
+                amount,
+            } => {
+                dest.push_byte(self.indices.asset_transfer_approved);
+                Compact(*id).encode_to(dest);
+                encode_address(owner, dest);
+                encode_address(destination, dest);
+                Compact(*amount).encode_to(dest);
+            }
+        }
+    }
+}
+
+/// In-memory PSP34 collections that stand in for the NFT contracts in the unit tests
+#[cfg(test)]
+mod mock_nft {
+    use openbrush::{
+        contracts::psp34::PSP34Error,
+        traits::{AccountId, String},
+    };
+    use std::{cell::RefCell, collections::BTreeMap};
+
+    struct Token {
+        owner: AccountId,
+        approved: Option<AccountId>,
+    }
+
+    thread_local! {
+        static TOKENS: RefCell<BTreeMap<(AccountId, u32), Token>> = RefCell::new(BTreeMap::new());
+    }
+
+    /// Mints the item of the collection to the owner
+    pub fn mint(nft_contract: AccountId, item_id: u32, owner: AccountId) {
+        TOKENS.with(|tokens| {
+            tokens.borrow_mut().insert(
+                (nft_contract, item_id),
+                Token {
+                    owner,
+                    approved: None,
+                },
+            )
+        });
+    }
+
+    /// Approves the operator to transfer the item on behalf of its owner
+    pub fn approve(nft_contract: AccountId, item_id: u32, operator: AccountId) {
+        TOKENS.with(|tokens| {
+            if let Some(token) = tokens.borrow_mut().get_mut(&(nft_contract, item_id)) {
+                token.approved = Some(operator);
+            }
+        });
+    }
+
+    pub fn owner_of(nft_contract: AccountId, item_id: u32) -> Option<AccountId> {
+        TOKENS.with(|tokens| {
+            tokens
+                .borrow()
+                .get(&(nft_contract, item_id))
+                .map(|token| token.owner)
+        })
+    }
+
+    /// Transfers the item like PSP34 does, the caller has to own the item or be approved for it
+    pub fn transfer(
+        nft_contract: AccountId,
+        caller: AccountId,
+        to: AccountId,
+        item_id: u32,
+    ) -> Result<(), PSP34Error> {
+        TOKENS.with(|tokens| {
+            let mut tokens = tokens.borrow_mut();
+            let token = tokens
+                .get_mut(&(nft_contract, item_id))
+                .ok_or(PSP34Error::TokenNotExists)?;
+            if token.owner != caller && token.approved != Some(caller) {
+                return Err(PSP34Error::Custom(String::from("NotApproved")));
+            }
+            token.owner = to;
+            token.approved = None;
+            Ok(())
+        })
+    }
+}
+
+/// In-memory PSP22 tokens that stand in for the token contracts in the unit tests
+#[cfg(test)]
+mod mock_psp22 {
+    use ink::primitives::AccountId;
+    use openbrush::contracts::psp22::PSP22Error;
+    use std::{cell::RefCell, collections::BTreeMap};
+
+    thread_local! {
+        static BALANCES: RefCell<BTreeMap<(AccountId, AccountId), u128>> = RefCell::new(BTreeMap::new());
+        static ALLOWANCES: RefCell<BTreeMap<(AccountId, AccountId, AccountId), u128>> = RefCell::new(BTreeMap::new());
+    }
+
+    /// Mints tokens to the owner
+    pub fn mint(token: AccountId, owner: AccountId, amount: u128) {
+        BALANCES
+            .with(|balances| *balances.borrow_mut().entry((token, owner)).or_default() += amount);
+    }
+
+    /// Approves the spender to transfer tokens on behalf of their owner
+    pub fn approve(token: AccountId, owner: AccountId, spender: AccountId, amount: u128) {
+        ALLOWANCES.with(|allowances| {
+            allowances
+                .borrow_mut()
+                .insert((token, owner, spender), amount)
+        });
+    }
+
+    pub fn balance_of(token: AccountId, owner: AccountId) -> u128 {
+        BALANCES.with(|balances| balances.borrow().get(&(token, owner)).copied().unwrap_or(0))
+    }
+
+    /// Transfers tokens of the caller like PSP22 does
+    pub fn transfer(
+        token: AccountId,
+        caller: AccountId,
+        to: AccountId,
+        amount: u128,
+    ) -> Result<(), PSP22Error> {
+        BALANCES.with(|balances| {
+            let mut balances = balances.borrow_mut();
+            let from_balance = balances.entry((token, caller)).or_default();
+            *from_balance = from_balance
+                .checked_sub(amount)
+                .ok_or(PSP22Error::InsufficientBalance)?;
+            *balances.entry((token, to)).or_default() += amount;
+            Ok(())
+        })
+    }
+
+    /// Transfers tokens like PSP22 does, the caller has to be approved for the amount
+    pub fn transfer_from(
+        token: AccountId,
+        caller: AccountId,
+        from: AccountId,
+        to: AccountId,
+        amount: u128,
+    ) -> Result<(), PSP22Error> {
+        ALLOWANCES.with(|allowances| {
+            let mut allowances = allowances.borrow_mut();
+            let allowance = allowances.entry((token, from, caller)).or_default();
+            let remaining = allowance
+                .checked_sub(amount)
+                .ok_or(PSP22Error::InsufficientAllowance)?;
+            transfer(token, from, to, amount)?;
+            *allowance = remaining;
+            Ok(())
+        })
+    }
+}
+
+/// Mock PSP34 and PSP22 contracts that receive the encoded cross-contract calls in the unit tests
+#[cfg(test)]
+mod mock_contracts {
+    use ink::{env::Error, prelude::vec::Vec, primitives::AccountId, LangError};
+    use openbrush::contracts::psp34::Id;
+    use scale::{Decode, Encode};
+
+    const PSP34_TRANSFER: [u8; 4] = ink::selector_bytes!("PSP34::transfer");
+    const PSP34_OWNER_OF: [u8; 4] = ink::selector_bytes!("PSP34::owner_of");
+    const PSP22_TRANSFER: [u8; 4] = ink::selector_bytes!("PSP22::transfer");
+    const PSP22_TRANSFER_FROM: [u8; 4] = ink::selector_bytes!("PSP22::transfer_from");
+
+    /// Decodes the selector and arguments of the call and returns the encoded message result
+    pub fn call(callee: AccountId, caller: AccountId, input: &[u8]) -> Result<Vec<u8>, Error> {
+        let (selector, mut args) = input.split_at(4);
+        let output = match <[u8; 4]>::try_from(selector).map_err(|_| Error::CalleeTrapped)? {
+            PSP34_TRANSFER => {
+                let (to, id, _data) = <(AccountId, Id, Vec<u8>)>::decode(&mut args)?;
+                let Id::U32(item_id) = id else {
+                    return Err(Error::CalleeTrapped);
+                };
+                Ok::<_, LangError>(crate::mock_nft::transfer(callee, caller, to, item_id)).encode()
+            }
+            PSP34_OWNER_OF => {
+                let Id::U32(item_id) = Id::decode(&mut args)? else {
+                    return Err(Error::CalleeTrapped);
+                };
+                Ok::<_, LangError>(crate::mock_nft::owner_of(callee, item_id)).encode()
+            }
+            PSP22_TRANSFER => {
+                let (to, amount, _data) = <(AccountId, u128, Vec<u8>)>::decode(&mut args)?;
+                Ok::<_, LangError>(crate::mock_psp22::transfer(callee, caller, to, amount)).encode()
+            }
+            PSP22_TRANSFER_FROM => {
+                let (from, to, amount, _data) =
+                    <(AccountId, AccountId, u128, Vec<u8>)>::decode(&mut args)?;
+                Ok::<_, LangError>(crate::mock_psp22::transfer_from(
+                    callee, caller, from, to, amount,
+                ))
+                .encode()
+            }
+            _ => return Err(Error::CalleeTrapped),
+        };
+        Ok(output)
+    }
+}
+
+/// Runtime stand-in that records the encoded pallet calls instead of dispatching them
+#[cfg(feature = "mock-runtime")]
+mod mock_runtime {
+    use std::cell::{Cell, RefCell};
+
+    thread_local! {
+        static CALLS: RefCell<Vec<Vec<u8>>> = RefCell::new(Vec::new());
+        static FAILING: Cell<bool> = Cell::new(false);
+    }
+
+    /// Records the call, or fails like the runtime does when the pallet returns an error
+    pub fn dispatch<Call: scale::Encode>(call: &Call) -> Result<(), ink::env::Error> {
+        if FAILING.with(Cell::get) {
+            return Err(ink::env::Error::CallRuntimeFailed);
+        }
+        CALLS.with(|calls| calls.borrow_mut().push(call.encode()));
+        Ok(())
+    }
+
+    /// Makes the following calls fail until it is reset
+    #[cfg(test)]
+    pub fn set_failing(failing: bool) {
+        FAILING.with(|cell| cell.set(failing));
+    }
+
+    /// Returns the encoded calls dispatched so far
+    #[cfg(test)]
+    pub fn calls() -> Vec<Vec<u8>> {
+        CALLS.with(|calls| calls.borrow().clone())
+    }
 }
 
+#[openbrush::implementation(AccessControl, Ownable)]
 #[openbrush::contract]
 pub mod loan {
 
-    use crate::{CommunityLoanPoolCall, RuntimeCall};
+    use crate::{AssetsCall, CommunityLoanPoolCall, RuntimeCall};
 
-    use ink::storage::Mapping;
-    use xcavate_lending_protocol::traits::loan::*;
+    use ink::{
+        env::{
+            call::{Call, CallParams},
+            DefaultEnvironment,
+        },
+        prelude::{vec, vec::Vec},
+        storage::{traits::StorageKey, Lazy, Mapping},
+    };
+    use xcavate_lending_protocol::traits::{loan::*, oracle::*};
 
-    use openbrush::traits::DefaultEnv;
+    use openbrush::traits::{DefaultEnv, Storage};
 
     type Id = u32;
+    //Collection ID, item ID and position of a valuation in the history of the nft
+    type ValuationKey = (u32, u32, u32);
 
+    /// Everything but the roles and the owner is kept in mappings and lazy cells, so the root
+    /// stays empty and decodes after every upgrade
     #[ink(storage)]
-    //#[derive(Default, Storage)]
+    #[derive(Storage)]
     pub struct LoanContract {
+        //Roles of the accounts that manage the contract
+        #[storage_field]
+        access: access_control::Data,
+        //Owner of the contract who can upgrade its code
+        #[storage_field]
+        ownable: ownable::Data,
         //Mapping of the loans
         loan_info: Mapping<Id, LoanInfo>,
+        //Value transferred above the amounts of the loans that the callers can withdraw
+        credits: Mapping<AccountId, Balance>,
+        //Sum of all credits, which can't be lent out
+        total_credit: Lazy<Balance>,
+        //Loans of every borrower by position
+        borrower_loans: Mapping<(AccountId, u32), Id>,
+        //Number of loans of every borrower
+        borrower_loan_count: Mapping<AccountId, u32>,
+        //Position of every loan in the loans of its borrower
+        borrower_loan_position: Mapping<Id, u32>,
+        //Loans of every lender by position
+        lender_loans: Mapping<(AccountId, u32), Id>,
+        //Number of loans of every lender
+        lender_loan_count: Mapping<AccountId, u32>,
+        //Position of every loan in the loans of its lender
+        lender_loan_position: Mapping<Id, u32>,
+        //Loans backed by the nfts of every collection by position
+        collection_loans: Mapping<(u32, u32), Id>,
+        //Number of loans backed by the nfts of every collection
+        collection_loan_count: Mapping<u32, u32>,
+        //Position of every loan in the loans of its collection
+        collection_loan_position: Mapping<Id, u32>,
+        //Aggregates over the loans of every asset
+        stats: Mapping<LoanAsset, PortfolioStats>,
+        //Funds of the native loans that the borrowers can still borrow
+        native_committed: Lazy<Balance>,
+        //Statuses every loan went through
+        status_history: Mapping<Id, Vec<StatusChange>>,
+        //Installments every loan has to be paid back with
+        schedules: Mapping<Id, Vec<Installment>>,
+        //Liquidations of the loans that have been liquidated
+        liquidations: Mapping<Id, Liquidation>,
+        //When loans can be liquidated and how the collateral and proceeds are shared
+        liquidation_config: Lazy<LiquidationConfig>,
+        //Valuations of the collateral nfts by collection, item and index in the history
+        valuations: Mapping<ValuationKey, Valuation>,
+        //Number of valuations of every collateral nft
+        valuation_count: Mapping<(u32, u32), u32>,
+        //Prices of the loan assets other than the native currency
+        asset_prices: Mapping<LoanAsset, AssetPrice>,
+        //Age after which a valuation is considered stale
+        max_valuation_age: Lazy<Timestamp>,
+        //Maximum loan to value ratio of new loans and drawdowns in basis points
+        max_ltv: Lazy<u32>,
+        //Maximum loan to value ratios of the collections that override the one of the contract
+        collection_max_ltv: Mapping<u32, u32>,
+        //Order in which repayments are applied to the parts of the debt, the default one if unset
+        payment_waterfall: Lazy<Vec<PaymentComponent>>,
+        //PSP34 contracts of the collections that are accepted as collateral
+        collections: Mapping<u32, AccountId>,
         //Identifier for the loan
-        last_loan_id: Id,
+        last_loan_id: Lazy<Id>,
         //AccountId of the community-loan-pool
-        pallet_id: AccountId,
+        pallet_id: Lazy<AccountId>,
+        //Indices of the community-loan-pool pallet and its calls in the runtime
+        call_indices: Lazy<RuntimeCallIndices>,
+        //Scopes in which the messages that move value are paused
+        paused: Mapping<PauseScope, ()>,
+        //Version of the layout the loans are stored with, unset until the root of an older version is migrated
+        storage_version: Lazy<u32>,
+        //Last loan that has been migrated to the current storage version
+        migrated_loan_id: Lazy<Id>,
+        //Number of decimals between a whole unit of the loan amounts and the balance of the chain
+        decimals: Lazy<u8>,
+        //Migrated loans of version 0 whose collateral has never been held in escrow
+        unescrowed_collateral: Mapping<Id, ()>,
+    }
+
+    /// Event emitted when a new loan has been created
+    #[ink(event)]
+    pub struct LoanCreated {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        borrower: AccountId,
+        #[ink(topic)]
+        lender: AccountId,
+        collection_id: u32,
+        item_id: u32,
+        collateral_price: Balance,
+        asset: LoanAsset,
+        available_amount: Balance,
+        interest_rate: u32,
+        repayment_type: RepaymentType,
+        maturity: Timestamp,
+    }
+
+    /// Event emitted when the valuation of a collateral nft has been refreshed
+    #[ink(event)]
+    pub struct ValuationUpdated {
+        #[ink(topic)]
+        collection_id: u32,
+        #[ink(topic)]
+        item_id: u32,
+        price: Balance,
+        timestamp: Timestamp,
+    }
+
+    /// Event emitted when the price of a loan asset has been refreshed
+    #[ink(event)]
+    pub struct AssetPriceUpdated {
+        #[ink(topic)]
+        asset: LoanAsset,
+        decimals: u8,
+        price: Balance,
+        timestamp: Timestamp,
+    }
+
+    /// Event emitted when the lender has added available funds to a loan
+    #[ink(event)]
+    pub struct LoanUpdated {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        borrower: AccountId,
+        #[ink(topic)]
+        lender: AccountId,
+        additional_available_amount: Balance,
+        available_amount: Balance,
+    }
+
+    /// Event emitted when the borrower has withdrawn funds from a loan
+    #[ink(event)]
+    pub struct FundsWithdrawn {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        borrower: AccountId,
+        #[ink(topic)]
+        lender: AccountId,
+        amount: Balance,
+        borrowed_amount: Balance,
+    }
+
+    /// Event emitted when the accrued interest has been charged to a loan
+    #[ink(event)]
+    pub struct InterestCharged {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        borrower: AccountId,
+        #[ink(topic)]
+        lender: AccountId,
+        interest: Balance,
+        borrowed_amount: Balance,
+    }
+
+    /// Event emitted when a fee or a penalty has been added to a loan
+    #[ink(event)]
+    pub struct ChargeAdded {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        borrower: AccountId,
+        #[ink(topic)]
+        lender: AccountId,
+        charge: Charge,
+        amount: Balance,
+        borrowed_amount: Balance,
+    }
+
+    /// Event emitted when the borrower has repaid a part of a loan
+    #[ink(event)]
+    pub struct LoanRepaid {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        borrower: AccountId,
+        #[ink(topic)]
+        lender: AccountId,
+        repay_amount: Balance,
+        paid: Debt,
+        borrowed_amount: Balance,
+    }
+
+    /// Event emitted when a loan moved to another status
+    #[ink(event)]
+    pub struct LoanStatusChanged {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        borrower: AccountId,
+        #[ink(topic)]
+        lender: AccountId,
+        previous_status: LoanStatus,
+        status: LoanStatus,
+    }
+
+    /// Event emitted when the collateral of a loan has been given back to the borrower
+    #[ink(event)]
+    pub struct CollateralReleased {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        borrower: AccountId,
+        #[ink(topic)]
+        lender: AccountId,
+        collection_id: u32,
+        item_id: u32,
+    }
+
+    /// Event emitted when a loan has been liquidated and its collateral taken over
+    #[ink(event)]
+    pub struct LoanLiquidated {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        borrower: AccountId,
+        #[ink(topic)]
+        lender: AccountId,
+        recipient: AccountId,
+        collection_id: u32,
+        item_id: u32,
+        debt: Balance,
+        shortfall: Balance,
+        returned_amount: Balance,
+    }
+
+    /// Event emitted when proceeds recovered from the collateral have been distributed
+    #[ink(event)]
+    pub struct RecoveryDistributed {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        borrower: AccountId,
+        #[ink(topic)]
+        lender: AccountId,
+        lender_amount: Balance,
+        pool_amount: Balance,
+        borrower_amount: Balance,
+        shortfall: Balance,
+    }
+
+    /// Event emitted when value transferred above the required amount has been credited
+    #[ink(event)]
+    pub struct CreditAdded {
+        #[ink(topic)]
+        account: AccountId,
+        amount: Balance,
+        credit: Balance,
+    }
+
+    /// Event emitted when an account has withdrawn its credit
+    #[ink(event)]
+    pub struct CreditWithdrawn {
+        #[ink(topic)]
+        account: AccountId,
+        amount: Balance,
+    }
+
+    /// Event emitted when the messages that move value have been paused for a scope
+    #[ink(event)]
+    pub struct Paused {
+        #[ink(topic)]
+        scope: PauseScope,
+        account: AccountId,
+    }
+
+    /// Event emitted when the messages that move value have been unpaused for a scope
+    #[ink(event)]
+    pub struct Unpaused {
+        #[ink(topic)]
+        scope: PauseScope,
+        account: AccountId,
+    }
+
+    /// Event emitted when the code of the contract has been replaced
+    #[ink(event)]
+    pub struct CodeUpgraded {
+        code_hash: [u8; 32],
+    }
+
+    /// Event emitted when every loan has been migrated to the current storage version
+    #[ink(event)]
+    pub struct StorageMigrated {
+        from_version: u32,
+        to_version: u32,
+    }
+
+    /// Event emitted when the lender has handed a loan over to a new lender
+    #[ink(event)]
+    pub struct LoanTransferred {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        from: AccountId,
+        #[ink(topic)]
+        to: AccountId,
+    }
+
+    /// Event emitted when a loan has been deleted
+    #[ink(event)]
+    pub struct LoanDeleted {
+        #[ink(topic)]
+        loan_id: Id,
+        #[ink(topic)]
+        borrower: AccountId,
+        #[ink(topic)]
+        lender: AccountId,
+        returned_amount: Balance,
     }
 
     impl Loan for LoanContract {
@@ -48,11 +658,61 @@ pub mod loan {
             item_id: u32,
             collateral_price: Balance,
             available_amount: Balance,
+            terms: LoanTerms,
+        ) -> Result<(), LoanError> {
+            self.create_asset_loan(
+                lender,
+                borrower,
+                collection_id,
+                item_id,
+                collateral_price,
+                available_amount,
+                terms,
+                LoanAsset::Native,
+            )
+        }
+
+        #[ink(message, payable)]
+        fn create_asset_loan(
+            &mut self,
+            lender: AccountId,
+            borrower: AccountId,
+            collection_id: u32,
+            item_id: u32,
+            collateral_price: Balance,
+            available_amount: Balance,
+            terms: LoanTerms,
+            asset: LoanAsset,
         ) -> Result<(), LoanError> {
-            if available_amount > Self::env().transferred_value() {
-                return Err(LoanError::NotEnoughFundsProvided);
+            self._ensure_role(LOAN_ORIGINATOR)?;
+            self._ensure_not_paused(&[PauseScope::Collection(collection_id)])?;
+            let excess_value = self._excess_value(&asset, available_amount)?;
+            let nft_contract = self
+                .collections
+                .get(collection_id)
+                .ok_or(LoanError::UnknownCollection)?;
+            if Self::_collateral_owner(nft_contract, item_id) != Some(borrower) {
+                return Err(LoanError::NotTheCollateralOwner);
+            }
+            // The oracle has to have valued the nft and the asset recently
+            let collateral_value = self._collateral_value_in(&asset, collection_id, item_id)?;
+            if Self::_exceeds_ltv(
+                available_amount,
+                collateral_value,
+                self.get_max_ltv(collection_id),
+            ) {
+                return Err(LoanError::LtvExceeded);
             }
             let timestamp = <Self as DefaultEnv>::env().block_timestamp();
+            let due_dates = Self::_due_dates(timestamp, &terms)?;
+            // The installments only get principal once the borrower draws funds
+            let schedule = Self::_build_installments(
+                &due_dates,
+                timestamp,
+                0,
+                terms.interest_rate,
+                terms.repayment_type,
+            )?;
             let borrowed_amount = 0;
             let loan_info = LoanInfo {
                 lender,
@@ -60,22 +720,60 @@ pub mod loan {
                 collection_id,
                 item_id,
                 collateral_price,
+                asset,
                 available_amount,
                 borrowed_amount,
+                debt: Debt::default(),
+                interest_rate: terms.interest_rate,
+                interest_remainder: 0,
+                status: LoanStatus::Pending,
+                repayment_type: terms.repayment_type,
+                payment_frequency: terms.payment_frequency,
+                start_date: timestamp,
+                maturity: terms.maturity,
                 timestamp,
             };
 
-            let loan_id = self._get_next_loan_id_and_increase();
+            let loan_id = self._get_next_loan_id_and_increase()?;
             if self.loan_info.get(loan_id).is_some() {
                 return Err(LoanError::LoanIdTaken);
             }
-            self.loan_info.insert(loan_id, &loan_info);
+            self._save_loan(loan_id, &loan_info)?;
+            self.schedules.insert(loan_id, &schedule);
+            self._index_loan(loan_id, &loan_info)?;
+            self.status_history.insert(
+                loan_id,
+                &vec![StatusChange {
+                    status: LoanStatus::Pending,
+                    timestamp,
+                }],
+            );
+            // The borrower has to approve the contract to take the nft into escrow
+            Self::_transfer_collateral(nft_contract, Self::env().account_id(), item_id)?;
+            self._receive_asset(&asset, Self::env().caller(), available_amount)?;
+            self._add_credit(Self::env().caller(), excess_value)?;
+            Self::env().emit_event(LoanCreated {
+                loan_id,
+                borrower,
+                lender,
+                collection_id,
+                item_id,
+                collateral_price,
+                asset,
+                available_amount,
+                interest_rate: terms.interest_rate,
+                repayment_type: terms.repayment_type,
+                maturity: terms.maturity,
+            });
             Ok(())
         }
 
         #[ink(message)]
         fn delete_loan(&mut self, loan_id: Id) -> Result<(), LoanError> {
-            let loan_info = self.loan_info.get(loan_id).unwrap();
+            self._ensure_role(LOAN_ORIGINATOR)?;
+            let mut loan_info = self._get_loan(loan_id)?;
+            self._ensure_loan_not_paused(loan_id, &loan_info)?;
+            Self::_accrue_interest(&mut loan_info)?;
             let remaining_available_amount = loan_info.available_amount;
             if loan_info.lender != Self::env().caller() {
                 return Err(LoanError::NoPermission);
@@ -83,17 +781,28 @@ pub mod loan {
             if loan_info.borrowed_amount != 0 {
                 return Err(LoanError::OngoingLoan);
             }
+            Self::_ensure_status(&loan_info, &[LoanStatus::Pending, LoanStatus::Repaid])?;
             if remaining_available_amount > 0 {
-                // for testing purpose on polkadot.js we add the zeros due to the decimals
-                <Self as DefaultEnv>::env()
-                    .transfer(self.pallet_id, remaining_available_amount * 1000000000000);
+                self._send_asset(
+                    &loan_info.asset,
+                    self._pallet_id()?,
+                    remaining_available_amount,
+                )?;
             }
-            self.loan_info.remove(loan_id);
-            Self::env()
-                .call_runtime(&RuntimeCall::CommunityLoanPool(
-                    CommunityLoanPoolCall::DeleteLoan { loan_id },
-                ))
-                .map_err(Into::into)
+            if loan_info.status == LoanStatus::Pending {
+                self._release_collateral(loan_id, &loan_info)?;
+            }
+            self._set_status(loan_id, &mut loan_info, LoanStatus::Closed)?;
+            self._remove_loan(loan_id)?;
+            self.schedules.remove(loan_id);
+            self._unindex_loan(loan_id, &loan_info);
+            Self::env().emit_event(LoanDeleted {
+                loan_id,
+                borrower: loan_info.borrower,
+                lender: loan_info.lender,
+                returned_amount: remaining_available_amount,
+            });
+            self._call_community_loan_pool(CommunityLoanPoolCall::DeleteLoan { loan_id })
         }
 
         #[ink(message, payable)]
@@ -102,58 +811,190 @@ pub mod loan {
             loan_id: Id,
             additional_available_amount: Balance,
         ) -> Result<(), LoanError> {
-            let mut loan_info = self.loan_info.get(loan_id).unwrap();
-            if additional_available_amount > Self::env().transferred_value() {
-                return Err(LoanError::NotEnoughFundsProvided);
-            }
+            self._ensure_role(LOAN_ORIGINATOR)?;
+            let mut loan_info = self._get_loan(loan_id)?;
+            self._ensure_loan_not_paused(loan_id, &loan_info)?;
+            let excess_value = self._excess_value(&loan_info.asset, additional_available_amount)?;
             if loan_info.lender != Self::env().caller() {
                 return Err(LoanError::NoPermission);
             }
-            loan_info.available_amount += additional_available_amount;
-            loan_info.timestamp = <Self as DefaultEnv>::env().block_timestamp();
-            self.loan_info.insert(loan_id, &loan_info);
+            Self::_ensure_status(&loan_info, &[LoanStatus::Pending, LoanStatus::Active])?;
+            let interest = Self::_accrue_interest(&mut loan_info)?;
+            // The funds the borrower could draw count towards the LTV as well
+            self._ensure_ltv(
+                &loan_info,
+                loan_info
+                    .borrowed_amount
+                    .saturating_add(loan_info.available_amount)
+                    .saturating_add(additional_available_amount),
+            )?;
+            loan_info.available_amount = loan_info
+                .available_amount
+                .checked_add(additional_available_amount)
+                .ok_or(LoanError::Overflow)?;
+            self._record_interest(&loan_info.asset, interest)?;
+            self._save_loan(loan_id, &loan_info)?;
+            self._receive_asset(
+                &loan_info.asset,
+                loan_info.lender,
+                additional_available_amount,
+            )?;
+            self._add_credit(loan_info.lender, excess_value)?;
+            Self::env().emit_event(LoanUpdated {
+                loan_id,
+                borrower: loan_info.borrower,
+                lender: loan_info.lender,
+                additional_available_amount,
+                available_amount: loan_info.available_amount,
+            });
             Ok(())
         }
 
-        #[ink(message, payable)]
-        fn charge_apy(&mut self, loan_id: Id, amount: Balance) -> Result<(), LoanError> {
-            let mut loan_info = self.loan_info.get(loan_id).unwrap();
-            loan_info.borrowed_amount += amount;
-            loan_info.timestamp = <Self as DefaultEnv>::env().block_timestamp();
-            self.loan_info.insert(loan_id, &loan_info);
+        #[ink(message)]
+        fn charge_apy(&mut self, loan_id: Id) -> Result<(), LoanError> {
+            self._ensure_role(INTEREST_KEEPER)?;
+            let mut loan_info = self._get_loan(loan_id)?;
+            Self::_ensure_status(
+                &loan_info,
+                &[
+                    LoanStatus::Pending,
+                    LoanStatus::Active,
+                    LoanStatus::Delinquent,
+                    LoanStatus::Defaulted,
+                ],
+            )?;
+            let interest = Self::_accrue_interest(&mut loan_info)?;
+            self._record_interest(&loan_info.asset, interest)?;
+            self._save_loan(loan_id, &loan_info)?;
+            let mut schedule = self.schedules.get(loan_id).unwrap_or_default();
+            Self::_mark_missed_installments(&mut schedule, loan_info.timestamp);
+            self.schedules.insert(loan_id, &schedule);
+            Self::env().emit_event(InterestCharged {
+                loan_id,
+                borrower: loan_info.borrower,
+                lender: loan_info.lender,
+                interest,
+                borrowed_amount: loan_info.borrowed_amount,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn add_charge(
+            &mut self,
+            loan_id: Id,
+            charge: Charge,
+            amount: Balance,
+        ) -> Result<(), LoanError> {
+            self._ensure_role(INTEREST_KEEPER)?;
+            if amount == 0 {
+                return Err(LoanError::ChargeMustBeHigherThanZero);
+            }
+            let mut loan_info = self._get_loan(loan_id)?;
+            Self::_ensure_status(
+                &loan_info,
+                &[
+                    LoanStatus::Active,
+                    LoanStatus::Delinquent,
+                    LoanStatus::Defaulted,
+                ],
+            )?;
+            let interest = Self::_accrue_interest(&mut loan_info)?;
+            loan_info.borrowed_amount = loan_info
+                .borrowed_amount
+                .checked_add(amount)
+                .ok_or(LoanError::Overflow)?;
+            loan_info.debt.add(charge.into(), amount)?;
+            self._record_interest(&loan_info.asset, interest)?;
+            self._save_loan(loan_id, &loan_info)?;
+            Self::env().emit_event(ChargeAdded {
+                loan_id,
+                borrower: loan_info.borrower,
+                lender: loan_info.lender,
+                charge,
+                amount,
+                borrowed_amount: loan_info.borrowed_amount,
+            });
             Ok(())
         }
 
         #[ink(message, payable)]
         fn repay(&mut self, loan_id: Id, repay_amount: Balance) -> Result<(), LoanError> {
-            let mut loan_info = self.loan_info.get(loan_id).unwrap();
+            let mut loan_info = self._get_loan(loan_id)?;
             if repay_amount == 0 {
                 return Err(LoanError::RepayAmountMustBeHigherThanZero);
             }
-            if repay_amount > Self::env().transferred_value() {
-                return Err(LoanError::NotEnoughFundsProvided);
+            let excess_value = self._excess_value(&loan_info.asset, repay_amount)?;
+            if loan_info.borrower != Self::env().caller() {
+                return Err(LoanError::NotTheBorrower);
             }
-            <Self as DefaultEnv>::env().transfer(self.pallet_id, Self::env().transferred_value());
-            loan_info.borrowed_amount -= repay_amount;
-            self.loan_info.insert(loan_id, &loan_info);
-            Self::env()
-                .call_runtime(&RuntimeCall::CommunityLoanPool(
-                    CommunityLoanPoolCall::UpdateLoan {
-                        loan_id,
-                        amount: repay_amount,
-                    },
-                ))
-                .map_err(Into::into)
+            Self::_ensure_status(
+                &loan_info,
+                &[
+                    LoanStatus::Active,
+                    LoanStatus::Delinquent,
+                    LoanStatus::Defaulted,
+                ],
+            )?;
+            let interest = Self::_accrue_interest(&mut loan_info)?;
+            loan_info.borrowed_amount = loan_info
+                .borrowed_amount
+                .checked_sub(repay_amount)
+                .ok_or(LoanError::RepayExceedsDebt)?;
+            let (paid, _) = loan_info
+                .debt
+                .pay(repay_amount, &self.get_payment_waterfall());
+            // The pallet is updated before anything else, so a failing call leaves the loan as it was
+            self._call_community_loan_pool(CommunityLoanPoolCall::UpdateLoan {
+                loan_id,
+                amount: repay_amount,
+            })?;
+            self._receive_asset(&loan_info.asset, loan_info.borrower, repay_amount)?;
+            self._send_asset(&loan_info.asset, self._pallet_id()?, repay_amount)?;
+            // Fees and penalties aren't part of the installments
+            let mut schedule = self.schedules.get(loan_id).unwrap_or_default();
+            Self::_mark_missed_installments(&mut schedule, loan_info.timestamp);
+            Self::_apply_payment(
+                &mut schedule,
+                paid.principal.saturating_add(paid.interest),
+                loan_info.timestamp,
+            );
+            self.schedules.insert(loan_id, &schedule);
+            if loan_info.borrowed_amount == 0 {
+                self._release_collateral(loan_id, &loan_info)?;
+                self._set_status(loan_id, &mut loan_info, LoanStatus::Repaid)?;
+            }
+            self._record_interest(&loan_info.asset, interest)?;
+            self._update_stats(&loan_info.asset, |stats| {
+                stats.total_repaid = stats
+                    .total_repaid
+                    .checked_add(repay_amount)
+                    .ok_or(LoanError::Overflow)?;
+                Ok(())
+            })?;
+            self._save_loan(loan_id, &loan_info)?;
+            self._add_credit(loan_info.borrower, excess_value)?;
+            Self::env().emit_event(LoanRepaid {
+                loan_id,
+                borrower: loan_info.borrower,
+                lender: loan_info.lender,
+                repay_amount,
+                paid,
+                borrowed_amount: loan_info.borrowed_amount,
+            });
+            Ok(())
         }
 
         #[ink(message)]
         fn withdraw_funds(&mut self, loan_id: Id, amount: u128) -> Result<(), LoanError> {
-            let loan_info_option = self.loan_info.get(loan_id);
-            if loan_info_option.is_none() {
-                return Err(LoanError::NonExistingLoanId);
-            }
-            let mut loan_info = loan_info_option.unwrap();
-            if amount > Self::env().balance() {
+            let mut loan_info = self._get_loan(loan_id)?;
+            self._ensure_loan_not_paused(loan_id, &loan_info)?;
+            let interest = Self::_accrue_interest(&mut loan_info)?;
+            // The loan may only draw the funds that are left after the credits of the callers
+            // and the funds committed to the other loans
+            if loan_info.asset == LoanAsset::Native
+                && self._to_planck(amount)? > self._own_funding(&loan_info)?
+            {
                 return Err(LoanError::InsufficientLoanBalance);
             }
             if amount > loan_info.available_amount {
@@ -162,249 +1003,5405 @@ pub mod loan {
             if loan_info.borrower != <Self as DefaultEnv>::env().caller() {
                 return Err(LoanError::NotTheBorrower);
             }
-            // for testing purpose on polkadot.js we add the zeros due to the decimals
-            <Self as DefaultEnv>::env().transfer(loan_info.borrower, amount * 1000000000000);
-            loan_info.borrowed_amount += amount;
-            loan_info.available_amount -= amount;
-            self.loan_info.insert(loan_id, &loan_info);
+            Self::_ensure_status(&loan_info, &[LoanStatus::Pending, LoanStatus::Active])?;
+            if loan_info.timestamp >= loan_info.maturity {
+                return Err(LoanError::LoanMatured);
+            }
+            let borrowed_amount = loan_info
+                .borrowed_amount
+                .checked_add(amount)
+                .ok_or(LoanError::Overflow)?;
+            self._ensure_ltv(&loan_info, borrowed_amount)?;
+            self._reschedule(loan_id, &loan_info, amount)?;
+            self._send_asset(&loan_info.asset, loan_info.borrower, amount)?;
+            loan_info.borrowed_amount = borrowed_amount;
+            loan_info.debt.add(PaymentComponent::Principal, amount)?;
+            loan_info.available_amount = loan_info
+                .available_amount
+                .checked_sub(amount)
+                .ok_or(LoanError::Overflow)?;
+            if loan_info.status == LoanStatus::Pending {
+                self._set_status(loan_id, &mut loan_info, LoanStatus::Active)?;
+            }
+            self._record_interest(&loan_info.asset, interest)?;
+            self._save_loan(loan_id, &loan_info)?;
+            Self::env().emit_event(FundsWithdrawn {
+                loan_id,
+                borrower: loan_info.borrower,
+                lender: loan_info.lender,
+                amount,
+                borrowed_amount: loan_info.borrowed_amount,
+            });
             Ok(())
         }
 
         #[ink(message)]
-        fn get_loan_info(&self, loan_id: Id) -> LoanInfo {
-            self.loan_info.get(loan_id).unwrap_or_else(|| {
-                panic!("loan_id doesn't exist");
+        fn liquidate(&mut self, loan_id: Id) -> Result<(), LoanError> {
+            self._ensure_role(LIQUIDATOR)?;
+            let mut loan_info = self._get_loan(loan_id)?;
+            self._ensure_loan_not_paused(loan_id, &loan_info)?;
+            let interest = Self::_accrue_interest(&mut loan_info)?;
+            Self::_ensure_status(
+                &loan_info,
+                &[
+                    LoanStatus::Active,
+                    LoanStatus::Delinquent,
+                    LoanStatus::Defaulted,
+                ],
+            )?;
+            let schedule = self.schedules.get(loan_id).unwrap_or_default();
+            let config = self.liquidation_config.get_or_default();
+            // A stale valuation can't make the loan liquidatable through its LTV
+            let collateral_value = self._collateral_value_in(
+                &loan_info.asset,
+                loan_info.collection_id,
+                loan_info.item_id,
+            );
+            if !Self::_is_liquidatable(
+                &loan_info,
+                &schedule,
+                &config,
+                collateral_value.as_ref().ok().copied(),
+                loan_info.timestamp,
+            ) {
+                return Err(LoanError::NotLiquidatable);
+            }
+            // The shortfall has to be based on a current valuation of the collateral
+            let collateral_value = collateral_value?;
+            let recipient = match config.collateral_recipient {
+                CollateralRecipient::Lender => loan_info.lender,
+                CollateralRecipient::CommunityLoanPool => self._pallet_id()?,
+            };
+            if loan_info.status != LoanStatus::Defaulted {
+                self._set_status(loan_id, &mut loan_info, LoanStatus::Defaulted)?;
+            }
+            self._set_status(loan_id, &mut loan_info, LoanStatus::Closed)?;
+            // The funds the borrower hasn't drawn go back like on delete_loan
+            let returned_amount = loan_info.available_amount;
+            loan_info.available_amount = 0;
+            self._record_interest(&loan_info.asset, interest)?;
+            self._save_loan(loan_id, &loan_info)?;
+            if returned_amount > 0 {
+                self._send_asset(&loan_info.asset, self._pallet_id()?, returned_amount)?;
+            }
+            let debt = loan_info.borrowed_amount;
+            let shortfall = debt.saturating_sub(collateral_value);
+            self.liquidations.insert(
+                loan_id,
+                &Liquidation {
+                    timestamp: loan_info.timestamp,
+                    recipient,
+                    debt,
+                    shortfall,
+                    recovered: 0,
+                },
+            );
+            self._hand_over_collateral(loan_id, &loan_info, recipient)?;
+            Self::env().emit_event(LoanLiquidated {
+                loan_id,
+                borrower: loan_info.borrower,
+                lender: loan_info.lender,
+                recipient,
+                collection_id: loan_info.collection_id,
+                item_id: loan_info.item_id,
+                debt,
+                shortfall,
+                returned_amount,
+            });
+            self._call_community_loan_pool(CommunityLoanPoolCall::LiquidateLoan {
+                loan_id,
+                shortfall,
             })
         }
-    }
 
-    impl LoanContract {
-        /// Constructor that initializes loan information for the contract
-        #[ink(constructor, payable)]
-        pub fn new(pallet_id: AccountId) -> Self {
-            let loan_info = Mapping::default();
-            let last_loan_id = 0;
+        #[ink(message, payable)]
+        fn distribute_recovery(&mut self, loan_id: Id, amount: Balance) -> Result<(), LoanError> {
+            self._ensure_role(LIQUIDATOR)?;
+            let loan_info = self._get_loan(loan_id)?;
+            self._ensure_loan_not_paused(loan_id, &loan_info)?;
+            let excess_value = self._excess_value(&loan_info.asset, amount)?;
+            let mut liquidation = self
+                .liquidations
+                .get(loan_id)
+                .ok_or(LoanError::NotLiquidated)?;
+            let (lender_amount, pool_amount, borrower_amount) = Self::_split_recovery(
+                liquidation.debt.saturating_sub(liquidation.recovered),
+                amount,
+                self.liquidation_config.get_or_default().lender_share,
+            )?;
+            liquidation.recovered = liquidation
+                .recovered
+                .checked_add(amount)
+                .ok_or(LoanError::Overflow)?;
+            self._receive_asset(&loan_info.asset, Self::env().caller(), amount)?;
+            for (account, part) in [
+                (loan_info.lender, lender_amount),
+                (self._pallet_id()?, pool_amount),
+                (loan_info.borrower, borrower_amount),
+            ] {
+                if part > 0 {
+                    self._send_asset(&loan_info.asset, account, part)?;
+                }
+            }
+            liquidation.shortfall = liquidation.debt.saturating_sub(liquidation.recovered);
+            self.liquidations.insert(loan_id, &liquidation);
+            self._add_credit(Self::env().caller(), excess_value)?;
+            Self::env().emit_event(RecoveryDistributed {
+                loan_id,
+                borrower: loan_info.borrower,
+                lender: loan_info.lender,
+                lender_amount,
+                pool_amount,
+                borrower_amount,
+                shortfall: liquidation.shortfall,
+            });
+            Ok(())
+        }
 
-            LoanContract {
-                loan_info,
-                last_loan_id,
-                pallet_id,
+        #[ink(message)]
+        fn get_liquidation(&self, loan_id: Id) -> Option<Liquidation> {
+            self.liquidations.get(loan_id)
+        }
+
+        #[ink(message)]
+        fn update_loan_status(&mut self, loan_id: Id, status: LoanStatus) -> Result<(), LoanError> {
+            self._ensure_role(ADMIN)?;
+            let mut loan_info = self._get_loan(loan_id)?;
+            // The other statuses are only reached through the loan functions
+            let manual_transition = match status {
+                LoanStatus::Delinquent | LoanStatus::Defaulted => true,
+                LoanStatus::Active => loan_info.status == LoanStatus::Delinquent,
+                _ => false,
+            };
+            if !manual_transition {
+                return Err(LoanError::InvalidStatusTransition);
             }
+            let interest = Self::_accrue_interest(&mut loan_info)?;
+            self._set_status(loan_id, &mut loan_info, status)?;
+            self._record_interest(&loan_info.asset, interest)?;
+            self._save_loan(loan_id, &loan_info)?;
+            Ok(())
         }
 
-        /// Internal function to return the id of a new loan and to increase it in the storage
-        fn _get_next_loan_id_and_increase(&mut self) -> u32 {
-            let mut loan_id = self.last_loan_id;
-            loan_id += 1;
-            self.last_loan_id = loan_id;
-            loan_id
+        #[ink(message)]
+        fn get_status_history(&self, loan_id: Id) -> Vec<StatusChange> {
+            self.status_history.get(loan_id).unwrap_or_default()
         }
-    }
 
-    #[cfg(test)]
-    mod tests {
-        use super::*;
-        use ink::env::pay_with_call;
-        use ink::env::test::*;
+        #[ink(message)]
+        fn get_schedule(&self, loan_id: Id) -> Vec<Installment> {
+            let mut schedule = self.schedules.get(loan_id).unwrap_or_default();
+            Self::_mark_missed_installments(&mut schedule, Self::env().block_timestamp());
+            schedule
+        }
 
-        fn create_contract() -> LoanContract {
-            let accounts = default_accounts();
-            set_sender(accounts.alice);
-            set_balance(contract_id(), 1000);
-            LoanContract::new(accounts.frank)
+        #[ink(message)]
+        fn next_amount_due(&self, loan_id: Id) -> Option<(Timestamp, Balance)> {
+            let schedule = self.schedules.get(loan_id)?;
+            let next = schedule
+                .iter()
+                .find(|installment| installment.amount_due() > 0)?;
+            // Overdue installments have to be paid together with the next one
+            let until = next.due_date.max(Self::env().block_timestamp());
+            let amount = schedule
+                .iter()
+                .filter(|installment| installment.due_date <= until)
+                .fold(0, |amount: Balance, installment| {
+                    amount.saturating_add(installment.amount_due())
+                });
+            Some((next.due_date, amount))
         }
 
-        fn contract_id() -> AccountId {
-            ink::env::test::callee::<ink::env::DefaultEnvironment>()
+        #[ink(message)]
+        fn current_ltv(&self, loan_id: Id) -> Result<u32, LoanError> {
+            let mut loan_info = self._get_loan(loan_id)?;
+            Self::_accrue_interest(&mut loan_info)?;
+            let value = self._collateral_value_in(
+                &loan_info.asset,
+                loan_info.collection_id,
+                loan_info.item_id,
+            )?;
+            let ltv = loan_info
+                .borrowed_amount
+                .saturating_mul(BASIS_POINTS as Balance)
+                .checked_div(value)
+                .unwrap_or(Balance::MAX);
+            Ok(ltv.min(u32::MAX as Balance) as u32)
         }
-        fn default_accounts() -> ink::env::test::DefaultAccounts<ink::env::DefaultEnvironment> {
-            ink::env::test::default_accounts::<ink::env::DefaultEnvironment>()
+
+        #[ink(message)]
+        fn get_loan_info(&self, loan_id: Id) -> LoanInfo {
+            self.try_get_loan_info(loan_id).unwrap_or_else(|| {
+                panic!("loan_id doesn't exist");
+            })
         }
-        fn set_sender(sender: AccountId) {
-            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(sender);
+
+        #[ink(message)]
+        fn query_version(&self) -> u32 {
+            QUERY_VERSION
         }
-        fn set_balance(account_id: AccountId, balance: Balance) {
-            ink::env::test::set_account_balance::<ink::env::DefaultEnvironment>(account_id, balance)
+
+        #[ink(message)]
+        fn try_get_loan_info(&self, loan_id: Id) -> Option<LoanInfo> {
+            let mut loan_info = self.loan_info.get(loan_id)?;
+            Self::_accrue_interest(&mut loan_info).ok()?;
+            Some(loan_info)
         }
 
-        #[ink::test]
-        fn create_loan_works() {
-            let accounts = default_accounts();
-            let mut loan = create_contract();
-            let result = pay_with_call!(
-                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000),
-                1000
-            );
-            assert_eq!(result, Ok(()));
+        #[ink(message)]
+        fn loan_exists(&self, loan_id: Id) -> bool {
+            self.loan_info.contains(loan_id)
         }
 
-        #[ink::test]
-        fn create_loan_fails_if_not_enough_funds_transferred() {
-            let accounts = default_accounts();
-            let mut loan = create_contract();
-            let result = pay_with_call!(
-                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000),
-                900
+        #[ink(message)]
+        fn loans_of_borrower(&self, borrower: AccountId, offset: u32, limit: u32) -> Vec<Id> {
+            let count = self.borrower_loan_count.get(borrower).unwrap_or(0);
+            Self::_index_page(
+                &self.borrower_loans,
+                borrower,
+                count,
+                offset,
+                limit.min(MAX_LOAN_PAGE),
+            )
+        }
+
+        #[ink(message)]
+        fn loans_by_borrower(
+            &self,
+            borrower: AccountId,
+            offset: u32,
+            limit: u32,
+        ) -> Vec<LoanSummary> {
+            let count = self.borrower_loan_count.get(borrower).unwrap_or(0);
+            let loan_ids = Self::_index_page(
+                &self.borrower_loans,
+                borrower,
+                count,
+                offset,
+                limit.min(MAX_LOAN_PAGE),
+            );
+            self._summaries(loan_ids)
+        }
+
+        #[ink(message)]
+        fn loans_by_lender(&self, lender: AccountId, offset: u32, limit: u32) -> Vec<LoanSummary> {
+            let count = self.lender_loan_count.get(lender).unwrap_or(0);
+            let loan_ids = Self::_index_page(
+                &self.lender_loans,
+                lender,
+                count,
+                offset,
+                limit.min(MAX_LOAN_PAGE),
+            );
+            self._summaries(loan_ids)
+        }
+
+        #[ink(message)]
+        fn loans_by_collection(
+            &self,
+            collection_id: u32,
+            offset: u32,
+            limit: u32,
+        ) -> Vec<LoanSummary> {
+            let count = self.collection_loan_count.get(collection_id).unwrap_or(0);
+            let loan_ids = Self::_index_page(
+                &self.collection_loans,
+                collection_id,
+                count,
+                offset,
+                limit.min(MAX_LOAN_PAGE),
+            );
+            self._summaries(loan_ids)
+        }
+
+        #[ink(message)]
+        fn portfolio_stats(&self, asset: LoanAsset) -> PortfolioStats {
+            self.stats.get(asset).unwrap_or_default()
+        }
+
+        #[ink(message)]
+        fn transfer_loan(&mut self, loan_id: Id, new_lender: AccountId) -> Result<(), LoanError> {
+            let mut loan_info = self._get_loan(loan_id)?;
+            if loan_info.lender != Self::env().caller() {
+                return Err(LoanError::NoPermission);
+            }
+            if new_lender == loan_info.lender {
+                return Err(LoanError::SameLender);
+            }
+            // The new lender has to be able to top up and delete the loan
+            if !AccessControl::has_role(self, LOAN_ORIGINATOR, Some(new_lender)) {
+                return Err(LoanError::NewLenderNotOriginator);
+            }
+            // Repaid and closed loans are only left to be deleted
+            Self::_ensure_status(
+                &loan_info,
+                &[
+                    LoanStatus::Pending,
+                    LoanStatus::Active,
+                    LoanStatus::Delinquent,
+                    LoanStatus::Defaulted,
+                ],
+            )?;
+            Self::_remove_from_index(
+                &mut self.lender_loans,
+                &mut self.lender_loan_count,
+                &mut self.lender_loan_position,
+                loan_info.lender,
+                loan_id,
+            );
+            Self::_add_to_index(
+                &mut self.lender_loans,
+                &mut self.lender_loan_count,
+                &mut self.lender_loan_position,
+                new_lender,
+                loan_id,
+            )?;
+            let previous_lender = loan_info.lender;
+            loan_info.lender = new_lender;
+            self._save_loan(loan_id, &loan_info)?;
+            Self::env().emit_event(LoanTransferred {
+                loan_id,
+                from: previous_lender,
+                to: new_lender,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn decimals(&self) -> u8 {
+            self.decimals.get_or_default()
+        }
+
+        #[ink(message)]
+        fn get_credit(&self, account: AccountId) -> Balance {
+            self.credits.get(account).unwrap_or(0)
+        }
+
+        #[ink(message)]
+        fn withdraw_credit(&mut self) -> Result<(), LoanError> {
+            self._ensure_not_paused(&[])?;
+            let caller = Self::env().caller();
+            let amount = self.get_credit(caller);
+            if amount == 0 {
+                return Err(LoanError::NoCredit);
+            }
+            let total_credit = self
+                .total_credit
+                .get_or_default()
+                .checked_sub(amount)
+                .ok_or(LoanError::Overflow)?;
+            <Self as DefaultEnv>::env().transfer(caller, amount)?;
+            self.credits.remove(caller);
+            self.total_credit.set(&total_credit);
+            Self::env().emit_event(CreditWithdrawn {
+                account: caller,
+                amount,
+            });
+            Ok(())
+        }
+    }
+
+    impl Oracle for LoanContract {
+        #[ink(message)]
+        fn update_valuation(
+            &mut self,
+            collection_id: u32,
+            item_id: u32,
+            price: Balance,
+        ) -> Result<(), LoanError> {
+            self._ensure_role(ORACLE)?;
+            if !self.collections.contains(collection_id) {
+                return Err(LoanError::UnknownCollection);
+            }
+            if price == 0 {
+                return Err(LoanError::InvalidValuation);
+            }
+            self._record_valuation(collection_id, item_id, price, Self::env().block_timestamp())
+        }
+
+        #[ink(message)]
+        fn get_valuation(&self, collection_id: u32, item_id: u32) -> Option<Valuation> {
+            let count = self.valuation_count.get((collection_id, item_id))?;
+            self.valuations.get((collection_id, item_id, count - 1))
+        }
+
+        #[ink(message)]
+        fn get_valuation_history(
+            &self,
+            collection_id: u32,
+            item_id: u32,
+            from: u32,
+            limit: u32,
+        ) -> Vec<Valuation> {
+            let count = self
+                .valuation_count
+                .get((collection_id, item_id))
+                .unwrap_or(0);
+            let to = from
+                .saturating_add(limit.min(MAX_VALUATION_PAGE))
+                .min(count);
+            (from..to)
+                .filter_map(|index| self.valuations.get((collection_id, item_id, index)))
+                .collect()
+        }
+
+        #[ink(message)]
+        fn update_asset_price(
+            &mut self,
+            asset: LoanAsset,
+            decimals: u8,
+            price: Balance,
+        ) -> Result<(), LoanError> {
+            self._ensure_role(ORACLE)?;
+            // Native amounts are in the whole units of the valuations already
+            if asset == LoanAsset::Native
+                || price == 0
+                || decimals > MAX_DECIMALS - ASSET_PRICE_DECIMALS
+            {
+                return Err(LoanError::InvalidValuation);
+            }
+            let timestamp = Self::env().block_timestamp();
+            self.asset_prices.insert(
+                asset,
+                &AssetPrice {
+                    decimals,
+                    price,
+                    timestamp,
+                },
+            );
+            Self::env().emit_event(AssetPriceUpdated {
+                asset,
+                decimals,
+                price,
+                timestamp,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        fn get_asset_price(&self, asset: LoanAsset) -> Option<AssetPrice> {
+            self.asset_prices.get(asset)
+        }
+
+        #[ink(message)]
+        fn max_valuation_age(&self) -> Timestamp {
+            self.max_valuation_age.get_or_default()
+        }
+    }
+
+    impl LoanContract {
+        /// Constructor that initializes loan information for the contract
+        /// The caller becomes the admin of the contract, who can grant the other roles, and its owner
+        /// Loan amounts are given in whole units, which are `10^decimals` of the chain balance
+        #[ink(constructor, payable)]
+        pub fn new(pallet_id: AccountId, decimals: u8) -> Self {
+            assert!(
+                decimals <= MAX_DECIMALS,
+                "decimals exceed the range of the balance"
+            );
+            let loan_info = Mapping::default();
+
+            let mut instance = LoanContract {
+                access: Default::default(),
+                ownable: Default::default(),
+                loan_info,
+                credits: Mapping::default(),
+                total_credit: Lazy::default(),
+                borrower_loans: Mapping::default(),
+                borrower_loan_count: Mapping::default(),
+                borrower_loan_position: Mapping::default(),
+                lender_loans: Mapping::default(),
+                lender_loan_count: Mapping::default(),
+                lender_loan_position: Mapping::default(),
+                collection_loans: Mapping::default(),
+                collection_loan_count: Mapping::default(),
+                collection_loan_position: Mapping::default(),
+                stats: Mapping::default(),
+                native_committed: Lazy::default(),
+                status_history: Mapping::default(),
+                schedules: Mapping::default(),
+                liquidations: Mapping::default(),
+                liquidation_config: Lazy::default(),
+                valuations: Mapping::default(),
+                valuation_count: Mapping::default(),
+                asset_prices: Mapping::default(),
+                max_valuation_age: Lazy::default(),
+                max_ltv: Lazy::default(),
+                collection_max_ltv: Mapping::default(),
+                payment_waterfall: Lazy::default(),
+                collections: Mapping::default(),
+                last_loan_id: Lazy::default(),
+                pallet_id: Lazy::default(),
+                call_indices: Lazy::default(),
+                paused: Mapping::default(),
+                storage_version: Lazy::default(),
+                migrated_loan_id: Lazy::default(),
+                decimals: Lazy::default(),
+                unescrowed_collateral: Mapping::default(),
+            };
+            instance.max_valuation_age.set(&DEFAULT_MAX_VALUATION_AGE);
+            instance.max_ltv.set(&DEFAULT_MAX_LTV);
+            instance.pallet_id.set(&pallet_id);
+            instance.storage_version.set(&STORAGE_VERSION);
+            instance.decimals.set(&decimals);
+            let caller = instance.env().caller();
+            access_control::Internal::_init_with_admin(&mut instance, Some(caller));
+            ownable::Internal::_init_with_owner(&mut instance, caller);
+            instance
+        }
+
+        /// Registers the PSP34 contract of a collection whose nfts can be used as collateral
+        #[ink(message)]
+        pub fn register_collection(
+            &mut self,
+            collection_id: u32,
+            nft_contract: AccountId,
+        ) -> Result<(), LoanError> {
+            self._ensure_role(ADMIN)?;
+            if self.collections.contains(collection_id) {
+                return Err(LoanError::CollectionAlreadyRegistered);
+            }
+            self.collections.insert(collection_id, &nft_contract);
+            Ok(())
+        }
+
+        /// Returns the PSP34 contract of a registered collection
+        #[ink(message)]
+        pub fn get_collection(&self, collection_id: u32) -> Option<AccountId> {
+            self.collections.get(collection_id)
+        }
+
+        /// Sets the indices of the community-loan-pool pallet and its calls after a runtime upgrade
+        #[ink(message)]
+        pub fn set_call_indices(&mut self, indices: RuntimeCallIndices) -> Result<(), LoanError> {
+            self._ensure_role(ADMIN)?;
+            if !indices.is_valid() {
+                return Err(LoanError::InvalidCallIndices);
+            }
+            self.call_indices.set(&indices);
+            Ok(())
+        }
+
+        /// Returns the indices of the community-loan-pool pallet and its calls
+        #[ink(message)]
+        pub fn get_call_indices(&self) -> RuntimeCallIndices {
+            self.call_indices.get_or_default()
+        }
+
+        /// Pauses the messages that move value for every loan, a single loan or a collection
+        /// Repayments are still accepted while paused
+        #[ink(message)]
+        pub fn pause(&mut self, scope: PauseScope) -> Result<(), LoanError> {
+            self._ensure_role(PAUSER)?;
+            if self.paused.contains(scope) {
+                return Err(LoanError::Paused(scope));
+            }
+            self.paused.insert(scope, &());
+            Self::env().emit_event(Paused {
+                scope,
+                account: Self::env().caller(),
+            });
+            Ok(())
+        }
+
+        /// Unpauses the messages that move value for the scope
+        #[ink(message)]
+        pub fn unpause(&mut self, scope: PauseScope) -> Result<(), LoanError> {
+            self._ensure_role(PAUSER)?;
+            if !self.paused.contains(scope) {
+                return Err(LoanError::NotPaused);
+            }
+            self.paused.remove(scope);
+            Self::env().emit_event(Unpaused {
+                scope,
+                account: Self::env().caller(),
+            });
+            Ok(())
+        }
+
+        /// Returns whether the scope itself is paused
+        #[ink(message)]
+        pub fn is_paused(&self, scope: PauseScope) -> bool {
+            self.paused.contains(scope)
+        }
+
+        /// Compares the balance of the contract with the funds committed to the native loans
+        /// and the credits, callable by the admin and auditors
+        #[ink(message)]
+        pub fn check_invariants(&self) -> Result<SolvencyReport, LoanError> {
+            if self._ensure_role(ADMIN).is_err() {
+                self._ensure_role(AUDITOR)?;
+            }
+            let balance = Self::env().balance();
+            let committed = self._to_planck(self.native_committed.get_or_default())?;
+            let credit = self.total_credit.get_or_default();
+            let deficit = committed
+                .checked_add(credit)
+                .ok_or(LoanError::Overflow)?
+                .saturating_sub(balance);
+            Ok(SolvencyReport {
+                balance,
+                committed,
+                credit,
+                deficit,
+            })
+        }
+
+        /// Replaces the code of the contract while keeping its storage and balance
+        /// Loans stored with an older layout have to be migrated with `migrate` afterwards
+        #[ink(message)]
+        pub fn upgrade(&mut self, code_hash: [u8; 32]) -> Result<(), LoanError> {
+            self._ensure_owner()?;
+            ink::env::set_code_hash(&code_hash)?;
+            Self::env().emit_event(CodeUpgraded { code_hash });
+            Ok(())
+        }
+
+        /// Returns the version of the layout the loans are stored with
+        #[ink(message)]
+        pub fn get_storage_version(&self) -> u32 {
+            self.storage_version.get_or_default()
+        }
+
+        /// Migrates up to `limit` loans stored with the layout of version 0 to the current one,
+        /// they are paid back with the given terms from now on
+        /// The first call after the upgrade moves the settings out of the old root first,
+        /// which has to happen before any other message changes the storage
+        /// Returns whether every loan has been migrated, the storage version is updated then
+        #[ink(message)]
+        pub fn migrate(&mut self, limit: u32, terms: LoanTerms) -> Result<bool, LoanError> {
+            if self.storage_version.get().is_none() {
+                self._migrate_root()?;
+            }
+            self._ensure_role(ADMIN)?;
+            let storage_version = self.storage_version.get_or_default();
+            if storage_version == STORAGE_VERSION {
+                return Err(LoanError::NothingToMigrate);
+            }
+            let timestamp = Self::env().block_timestamp();
+            let migrated_loan_id = self.migrated_loan_id.get_or_default();
+            let loan_count = self.last_loan_id.get_or_default();
+            let last_loan_id = migrated_loan_id.saturating_add(limit).min(loan_count);
+            for loan_id in migrated_loan_id + 1..=last_loan_id {
+                self._migrate_loan(loan_id, &terms, timestamp)?;
+            }
+            if last_loan_id < loan_count {
+                self.migrated_loan_id.set(&last_loan_id);
+                return Ok(false);
+            }
+            Self::env().emit_event(StorageMigrated {
+                from_version: storage_version,
+                to_version: STORAGE_VERSION,
+            });
+            self.storage_version.set(&STORAGE_VERSION);
+            self.migrated_loan_id.set(&0);
+            Ok(true)
+        }
+
+        /// Internal function to move the settings that storage version 0 kept in the root into
+        /// the lazy cells
+        /// Version 0 had no roles, so the community-loan-pool account becomes the admin, and the
+        /// admin who migrates the root becomes the owner of the contract
+        fn _migrate_root(&mut self) -> Result<(), LoanError> {
+            let key = <Self as StorageKey>::KEY;
+            if ink::env::contains_contract_storage(&key) != Some(RootV0::ENCODED_SIZE) {
+                return Err(LoanError::DecodeFailed);
+            }
+            let root =
+                ink::env::get_contract_storage::<_, RootV0>(&key)?.ok_or(LoanError::KeyNotFound)?;
+            access_control::Internal::_init_with_admin(self, Some(root.pallet_id));
+            let caller = Self::env().caller();
+            if !AccessControl::has_role(self, ADMIN, Some(caller)) {
+                return Err(LoanError::MissingRole(ADMIN));
+            }
+            // Version 0 had no other settings and scaled the loan amounts by 10^12
+            self.max_valuation_age.set(&DEFAULT_MAX_VALUATION_AGE);
+            self.max_ltv.set(&DEFAULT_MAX_LTV);
+            self.last_loan_id.set(&root.last_loan_id);
+            self.pallet_id.set(&root.pallet_id);
+            self.storage_version.set(&0);
+            self.decimals.set(&12);
+            ownable::Internal::_init_with_owner(self, caller);
+            Ok(())
+        }
+
+        /// Internal function to migrate a loan from storage version 0, deleted loans are skipped
+        fn _migrate_loan(
+            &mut self,
+            loan_id: Id,
+            terms: &LoanTerms,
+            timestamp: Timestamp,
+        ) -> Result<(), LoanError> {
+            // The stored loan can't be decoded as a LoanInfo, so it is read from its raw key
+            let key = (StorageKey::key(&self.loan_info), loan_id);
+            let Some(loan_v0) = ink::env::get_contract_storage::<_, LoanInfoV0>(&key)? else {
+                return Ok(());
+            };
+            // Funds that haven't been drawn yet are added to the installments when they are
+            let principal = loan_v0.borrowed_amount;
+            let loan_info = loan_v0.migrate(terms, timestamp);
+            let due_dates = Self::_due_dates(timestamp, terms)?;
+            let schedule = Self::_build_installments(
+                &due_dates,
+                timestamp,
+                principal,
+                terms.interest_rate,
+                terms.repayment_type,
+            )?;
+            self._add_to_stats(&loan_info)?;
+            self.loan_info.insert(loan_id, &loan_info);
+            self.schedules.insert(loan_id, &schedule);
+            // The collateral stayed with the borrower and the price the loan was given for is its
+            // valuation until the oracle updates it
+            self.unescrowed_collateral.insert(loan_id, &());
+            self._record_valuation(
+                loan_info.collection_id,
+                loan_info.item_id,
+                loan_info.collateral_price,
+                timestamp,
+            )?;
+            self.status_history.insert(
+                loan_id,
+                &vec![StatusChange {
+                    status: loan_info.status,
+                    timestamp,
+                }],
+            );
+            self._index_loan(loan_id, &loan_info)
+        }
+
+        /// Internal function to add a new loan to the indexes of its borrower, lender and collection
+        fn _index_loan(&mut self, loan_id: Id, loan_info: &LoanInfo) -> Result<(), LoanError> {
+            Self::_add_to_index(
+                &mut self.borrower_loans,
+                &mut self.borrower_loan_count,
+                &mut self.borrower_loan_position,
+                loan_info.borrower,
+                loan_id,
+            )?;
+            Self::_add_to_index(
+                &mut self.lender_loans,
+                &mut self.lender_loan_count,
+                &mut self.lender_loan_position,
+                loan_info.lender,
+                loan_id,
+            )?;
+            Self::_add_to_index(
+                &mut self.collection_loans,
+                &mut self.collection_loan_count,
+                &mut self.collection_loan_position,
+                loan_info.collection_id,
+                loan_id,
+            )
+        }
+
+        /// Internal function to remove a deleted loan from the indexes of its borrower, lender
+        /// and collection
+        fn _unindex_loan(&mut self, loan_id: Id, loan_info: &LoanInfo) {
+            Self::_remove_from_index(
+                &mut self.borrower_loans,
+                &mut self.borrower_loan_count,
+                &mut self.borrower_loan_position,
+                loan_info.borrower,
+                loan_id,
+            );
+            Self::_remove_from_index(
+                &mut self.lender_loans,
+                &mut self.lender_loan_count,
+                &mut self.lender_loan_position,
+                loan_info.lender,
+                loan_id,
+            );
+            Self::_remove_from_index(
+                &mut self.collection_loans,
+                &mut self.collection_loan_count,
+                &mut self.collection_loan_position,
+                loan_info.collection_id,
+                loan_id,
+            );
+        }
+
+        /// Internal function to append a loan to the loans of an index entry
+        fn _add_to_index<K, LoansKey, CountKey, PositionKey>(
+            loans: &mut Mapping<(K, u32), Id, LoansKey>,
+            count: &mut Mapping<K, u32, CountKey>,
+            positions: &mut Mapping<Id, u32, PositionKey>,
+            key: K,
+            loan_id: Id,
+        ) -> Result<(), LoanError>
+        where
+            K: scale::EncodeLike + Copy,
+            LoansKey: StorageKey,
+            CountKey: StorageKey,
+            PositionKey: StorageKey,
+        {
+            let position = count.get(key).unwrap_or(0);
+            let next_count = position.checked_add(1).ok_or(LoanError::Overflow)?;
+            loans.insert((key, position), &loan_id);
+            positions.insert(loan_id, &position);
+            count.insert(key, &next_count);
+            Ok(())
+        }
+
+        /// Internal function to remove a loan from the loans of an index entry
+        /// The last loan of the entry takes its position, so the order of the loans changes
+        fn _remove_from_index<K, LoansKey, CountKey, PositionKey>(
+            loans: &mut Mapping<(K, u32), Id, LoansKey>,
+            count: &mut Mapping<K, u32, CountKey>,
+            positions: &mut Mapping<Id, u32, PositionKey>,
+            key: K,
+            loan_id: Id,
+        ) where
+            K: scale::EncodeLike + Copy,
+            LoansKey: StorageKey,
+            CountKey: StorageKey,
+            PositionKey: StorageKey,
+        {
+            let (Some(position), Some(length)) = (positions.take(loan_id), count.get(key)) else {
+                return;
+            };
+            let last = length - 1;
+            if position != last {
+                if let Some(last_loan_id) = loans.get((key, last)) {
+                    loans.insert((key, position), &last_loan_id);
+                    positions.insert(last_loan_id, &position);
+                }
+            }
+            loans.remove((key, last));
+            if last == 0 {
+                count.remove(key);
+            } else {
+                count.insert(key, &last);
+            }
+        }
+
+        /// Internal function to load the loans of an index entry at up to `limit` positions
+        /// from `offset`, reading only the positions of the page
+        fn _index_page<K, LoansKey>(
+            loans: &Mapping<(K, u32), Id, LoansKey>,
+            key: K,
+            count: u32,
+            offset: u32,
+            limit: u32,
+        ) -> Vec<Id>
+        where
+            K: scale::EncodeLike + Copy,
+            LoansKey: StorageKey,
+        {
+            (offset..count.min(offset.saturating_add(limit)))
+                .filter_map(|position| loans.get((key, position)))
+                .collect()
+        }
+
+        /// Internal function to return the summaries of a page of loans
+        fn _summaries(&self, loan_ids: Vec<Id>) -> Vec<LoanSummary> {
+            loan_ids
+                .into_iter()
+                .filter_map(|loan_id| {
+                    self.try_get_loan_info(loan_id)
+                        .map(|loan_info| loan_info.summary(loan_id))
+                })
+                .collect()
+        }
+
+        /// Internal function to check that neither all loans nor one of the scopes are paused
+        fn _ensure_not_paused(&self, scopes: &[PauseScope]) -> Result<(), LoanError> {
+            self._ensure_root_migrated()?;
+            for &scope in [PauseScope::All].iter().chain(scopes) {
+                if self.paused.contains(scope) {
+                    return Err(LoanError::Paused(scope));
+                }
+            }
+            Ok(())
+        }
+
+        /// Internal function to check that the loan and its collection are not paused
+        fn _ensure_loan_not_paused(
+            &self,
+            loan_id: Id,
+            loan_info: &LoanInfo,
+        ) -> Result<(), LoanError> {
+            self._ensure_not_paused(&[
+                PauseScope::Loan(loan_id),
+                PauseScope::Collection(loan_info.collection_id),
+            ])
+        }
+
+        /// Internal function to dispatch a call of the community-loan-pool pallet
+        fn _call_community_loan_pool(&self, call: CommunityLoanPoolCall) -> Result<(), LoanError> {
+            self._call_runtime(call)
+        }
+
+        /// Internal function to dispatch a call of the assets pallet
+        fn _call_assets(&self, call: AssetsCall) -> Result<(), LoanError> {
+            self._call_runtime(call)
+        }
+
+        /// Internal function to dispatch a call with the indices of the current runtime
+        #[cfg(not(any(feature = "mock-runtime", feature = "permissive-node")))]
+        fn _call_runtime<Call>(&self, call: Call) -> Result<(), LoanError>
+        where
+            RuntimeCall<Call>: scale::Encode,
+        {
+            Self::env()
+                .call_runtime(&RuntimeCall {
+                    indices: self.call_indices.get_or_default(),
+                    call,
+                })
+                .map_err(Into::into)
+        }
+
+        #[cfg(feature = "mock-runtime")]
+        fn _call_runtime<Call>(&self, call: Call) -> Result<(), LoanError>
+        where
+            RuntimeCall<Call>: scale::Encode,
+        {
+            crate::mock_runtime::dispatch(&RuntimeCall {
+                indices: self.call_indices.get_or_default(),
+                call,
+            })
+            .map_err(Into::into)
+        }
+
+        // Test builds for nodes without the community-loan-pool pallet, like the one of the
+        // e2e tests, only encode the calls and accept them
+        #[cfg(all(feature = "permissive-node", not(feature = "mock-runtime")))]
+        fn _call_runtime<Call>(&self, call: Call) -> Result<(), LoanError>
+        where
+            RuntimeCall<Call>: scale::Encode,
+        {
+            scale::Encode::encode(&RuntimeCall {
+                indices: self.call_indices.get_or_default(),
+                call,
+            });
+            Ok(())
+        }
+
+        /// Sets the maximum loan to value ratio of new loans and drawdowns in basis points
+        #[ink(message)]
+        pub fn set_max_ltv(&mut self, max_ltv: u32) -> Result<(), LoanError> {
+            self._ensure_role(ADMIN)?;
+            if max_ltv == 0 || max_ltv > BASIS_POINTS {
+                return Err(LoanError::InvalidLtv);
+            }
+            self.max_ltv.set(&max_ltv);
+            Ok(())
+        }
+
+        /// Sets the order in which repayments are applied to the parts of the debt,
+        /// every part has to be named once
+        #[ink(message)]
+        pub fn set_payment_waterfall(
+            &mut self,
+            waterfall: Vec<PaymentComponent>,
+        ) -> Result<(), LoanError> {
+            self._ensure_role(ADMIN)?;
+            if waterfall.len() != DEFAULT_PAYMENT_WATERFALL.len()
+                || DEFAULT_PAYMENT_WATERFALL
+                    .iter()
+                    .any(|component| !waterfall.contains(component))
+            {
+                return Err(LoanError::InvalidWaterfall);
+            }
+            self.payment_waterfall.set(&waterfall);
+            Ok(())
+        }
+
+        /// Returns the order in which repayments are applied to the parts of the debt
+        #[ink(message)]
+        pub fn get_payment_waterfall(&self) -> Vec<PaymentComponent> {
+            self.payment_waterfall
+                .get()
+                .unwrap_or_else(|| DEFAULT_PAYMENT_WATERFALL.to_vec())
+        }
+
+        /// Overrides the maximum loan to value ratio for the loans of a collection,
+        /// `None` removes the override
+        #[ink(message)]
+        pub fn set_collection_max_ltv(
+            &mut self,
+            collection_id: u32,
+            max_ltv: Option<u32>,
+        ) -> Result<(), LoanError> {
+            self._ensure_role(ADMIN)?;
+            match max_ltv {
+                Some(max_ltv) => {
+                    if max_ltv == 0 || max_ltv > BASIS_POINTS {
+                        return Err(LoanError::InvalidLtv);
+                    }
+                    self.collection_max_ltv.insert(collection_id, &max_ltv);
+                }
+                None => self.collection_max_ltv.remove(collection_id),
+            }
+            Ok(())
+        }
+
+        /// Returns the maximum loan to value ratio that applies to the loans of a collection
+        #[ink(message)]
+        pub fn get_max_ltv(&self, collection_id: u32) -> u32 {
+            self.collection_max_ltv
+                .get(collection_id)
+                .unwrap_or_else(|| self.max_ltv.get_or_default())
+        }
+
+        /// Internal function to check that an amount owed on the loan stays within the
+        /// maximum loan to value ratio of its collection
+        fn _ensure_ltv(&self, loan_info: &LoanInfo, amount: Balance) -> Result<(), LoanError> {
+            let value = self._collateral_value_in(
+                &loan_info.asset,
+                loan_info.collection_id,
+                loan_info.item_id,
+            )?;
+            if Self::_exceeds_ltv(amount, value, self.get_max_ltv(loan_info.collection_id)) {
+                return Err(LoanError::LtvExceeded);
+            }
+            Ok(())
+        }
+
+        /// Internal function to check whether an amount is above the loan to value ratio of a value
+        fn _exceeds_ltv(amount: Balance, value: Balance, ltv: u32) -> bool {
+            amount.saturating_mul(BASIS_POINTS as Balance) > value.saturating_mul(ltv as Balance)
+        }
+
+        /// Sets when loans can be liquidated and how the collateral and proceeds are shared
+        #[ink(message)]
+        pub fn set_liquidation_config(
+            &mut self,
+            config: LiquidationConfig,
+        ) -> Result<(), LoanError> {
+            self._ensure_role(ADMIN)?;
+            if config.max_ltv == 0 || config.lender_share > BASIS_POINTS {
+                return Err(LoanError::InvalidLiquidationConfig);
+            }
+            self.liquidation_config.set(&config);
+            Ok(())
+        }
+
+        /// Returns when loans can be liquidated and how the collateral and proceeds are shared
+        #[ink(message)]
+        pub fn get_liquidation_config(&self) -> LiquidationConfig {
+            self.liquidation_config.get_or_default()
+        }
+
+        /// Sets how old a valuation may be before it is considered stale
+        #[ink(message)]
+        pub fn set_max_valuation_age(
+            &mut self,
+            max_valuation_age: Timestamp,
+        ) -> Result<(), LoanError> {
+            self._ensure_role(ADMIN)?;
+            self.max_valuation_age.set(&max_valuation_age);
+            Ok(())
+        }
+
+        /// Internal function to append a valuation to the history of a collateral nft
+        fn _record_valuation(
+            &mut self,
+            collection_id: u32,
+            item_id: u32,
+            price: Balance,
+            timestamp: Timestamp,
+        ) -> Result<(), LoanError> {
+            let count = self
+                .valuation_count
+                .get((collection_id, item_id))
+                .unwrap_or(0);
+            let next_count = count.checked_add(1).ok_or(LoanError::Overflow)?;
+            self.valuations.insert(
+                (collection_id, item_id, count),
+                &Valuation { price, timestamp },
+            );
+            self.valuation_count
+                .insert((collection_id, item_id), &next_count);
+            Self::env().emit_event(ValuationUpdated {
+                collection_id,
+                item_id,
+                price,
+                timestamp,
+            });
+            Ok(())
+        }
+
+        /// Internal function to return the current value of a collateral nft
+        /// Fails if there is no valuation or the latest one is older than the maximum valuation age
+        fn _collateral_value(
+            &self,
+            collection_id: u32,
+            item_id: u32,
+        ) -> Result<Balance, LoanError> {
+            let valuation = self
+                .get_valuation(collection_id, item_id)
+                .ok_or(LoanError::StaleValuation)?;
+            self._ensure_fresh(valuation.timestamp)?;
+            Ok(valuation.price)
+        }
+
+        /// Internal function to return the current value of a collateral nft in the amounts of
+        /// the loans in an asset, whole units for the native currency and the smallest unit of
+        /// any other asset, which has to have a price that isn't stale
+        fn _collateral_value_in(
+            &self,
+            asset: &LoanAsset,
+            collection_id: u32,
+            item_id: u32,
+        ) -> Result<Balance, LoanError> {
+            let value = self._collateral_value(collection_id, item_id)?;
+            if *asset == LoanAsset::Native {
+                return Ok(value);
+            }
+            let asset_price = self
+                .asset_prices
+                .get(asset)
+                .ok_or(LoanError::StaleValuation)?;
+            self._ensure_fresh(asset_price.timestamp)?;
+            let scale = 10u128.pow(u32::from(asset_price.decimals + ASSET_PRICE_DECIMALS));
+            value
+                .checked_mul(scale)
+                .map(|scaled| scaled / asset_price.price)
+                .ok_or(LoanError::Overflow)
+        }
+
+        /// Internal function to check that a valuation or price isn't older than the maximum
+        /// valuation age
+        fn _ensure_fresh(&self, timestamp: Timestamp) -> Result<(), LoanError> {
+            let age = Self::env().block_timestamp().saturating_sub(timestamp);
+            if age > self.max_valuation_age.get_or_default() {
+                return Err(LoanError::StaleValuation);
+            }
+            Ok(())
+        }
+
+        /// Internal function to check whether a loan is past the grace period of an unpaid
+        /// installment or above the maximum loan to value ratio
+        fn _is_liquidatable(
+            loan_info: &LoanInfo,
+            schedule: &[Installment],
+            config: &LiquidationConfig,
+            collateral_value: Option<Balance>,
+            now: Timestamp,
+        ) -> bool {
+            let past_grace_period = schedule.iter().any(|installment| {
+                installment.amount_due() > 0
+                    && installment.due_date.saturating_add(config.grace_period) < now
+            });
+            let above_max_ltv = match collateral_value {
+                Some(value) => Self::_exceeds_ltv(loan_info.borrowed_amount, value, config.max_ltv),
+                None => false,
+            };
+            loan_info.status == LoanStatus::Defaulted || past_grace_period || above_max_ltv
+        }
+
+        /// Internal function to split recovered proceeds into the parts of the lender,
+        /// the community-loan-pool and the borrower
+        /// Only the outstanding debt is shared, anything above it belongs to the borrower
+        fn _split_recovery(
+            outstanding: Balance,
+            amount: Balance,
+            lender_share: u32,
+        ) -> Result<(Balance, Balance, Balance), LoanError> {
+            let covered = amount.min(outstanding);
+            let lender_amount = covered
+                .checked_mul(lender_share as Balance)
+                .ok_or(LoanError::Overflow)?
+                / BASIS_POINTS as Balance;
+            Ok((lender_amount, covered - lender_amount, amount - covered))
+        }
+
+        /// Internal function to load a loan, failing if there is no loan for the loan_id
+        fn _get_loan(&self, loan_id: Id) -> Result<LoanInfo, LoanError> {
+            self.loan_info
+                .get(loan_id)
+                .ok_or(LoanError::NonExistingLoanId)
+        }
+
+        /// Internal function to store a loan and replace its previous version in the aggregates
+        fn _save_loan(&mut self, loan_id: Id, loan_info: &LoanInfo) -> Result<(), LoanError> {
+            if let Some(previous) = self.loan_info.get(loan_id) {
+                self._remove_from_stats(&previous)?;
+            }
+            self._add_to_stats(loan_info)?;
+            self.loan_info.insert(loan_id, loan_info);
+            Ok(())
+        }
+
+        /// Internal function to delete a loan and remove it from the aggregates
+        fn _remove_loan(&mut self, loan_id: Id) -> Result<(), LoanError> {
+            let loan_info = self._get_loan(loan_id)?;
+            self._remove_from_stats(&loan_info)?;
+            self.loan_info.remove(loan_id);
+            self.unescrowed_collateral.remove(loan_id);
+            Ok(())
+        }
+
+        /// Internal function to add a loan to the aggregates and the committed native funds
+        fn _add_to_stats(&mut self, loan_info: &LoanInfo) -> Result<(), LoanError> {
+            self._update_stats(&loan_info.asset, |stats| stats.add_loan(loan_info))?;
+            if loan_info.asset == LoanAsset::Native {
+                let native_committed = self
+                    .native_committed
+                    .get_or_default()
+                    .checked_add(loan_info.available_amount)
+                    .ok_or(LoanError::Overflow)?;
+                self.native_committed.set(&native_committed);
+            }
+            Ok(())
+        }
+
+        /// Internal function to remove a loan from the aggregates and the committed native funds
+        fn _remove_from_stats(&mut self, loan_info: &LoanInfo) -> Result<(), LoanError> {
+            self._update_stats(&loan_info.asset, |stats| stats.remove_loan(loan_info))?;
+            if loan_info.asset == LoanAsset::Native {
+                let native_committed = self
+                    .native_committed
+                    .get_or_default()
+                    .checked_sub(loan_info.available_amount)
+                    .ok_or(LoanError::Overflow)?;
+                self.native_committed.set(&native_committed);
+            }
+            Ok(())
+        }
+
+        /// Internal function to return the part of the balance that funds a native loan,
+        /// which is what is left after the credits and the funds committed to the other loans
+        fn _own_funding(&self, loan_info: &LoanInfo) -> Result<Balance, LoanError> {
+            let other_loans = self._to_planck(
+                self.native_committed
+                    .get_or_default()
+                    .saturating_sub(loan_info.available_amount),
+            )?;
+            Ok(Self::env()
+                .balance()
+                .saturating_sub(self.total_credit.get_or_default())
+                .saturating_sub(other_loans))
+        }
+
+        /// Internal function to add the interest charged on a loan to the aggregates of its asset
+        fn _record_interest(
+            &mut self,
+            asset: &LoanAsset,
+            interest: Balance,
+        ) -> Result<(), LoanError> {
+            self._update_stats(asset, |stats| {
+                stats.total_interest = stats
+                    .total_interest
+                    .checked_add(interest)
+                    .ok_or(LoanError::Overflow)?;
+                Ok(())
+            })
+        }
+
+        /// Internal function to change the aggregates of the loans in an asset
+        fn _update_stats(
+            &mut self,
+            asset: &LoanAsset,
+            update: impl FnOnce(&mut PortfolioStats) -> Result<(), LoanError>,
+        ) -> Result<(), LoanError> {
+            let mut stats = self.stats.get(asset).unwrap_or_default();
+            update(&mut stats)?;
+            self.stats.insert(asset, &stats);
+            Ok(())
+        }
+
+        /// Internal function to move a loan to a new status and record it in the history
+        fn _set_status(
+            &mut self,
+            loan_id: Id,
+            loan_info: &mut LoanInfo,
+            status: LoanStatus,
+        ) -> Result<(), LoanError> {
+            let previous_status = loan_info.status;
+            if !previous_status.can_transition_to(status) {
+                return Err(LoanError::InvalidStatusTransition);
+            }
+            loan_info.status = status;
+            let mut history = self.status_history.get(loan_id).unwrap_or_default();
+            history.push(StatusChange {
+                status,
+                timestamp: Self::env().block_timestamp(),
+            });
+            self.status_history.insert(loan_id, &history);
+            Self::env().emit_event(LoanStatusChanged {
+                loan_id,
+                borrower: loan_info.borrower,
+                lender: loan_info.lender,
+                previous_status,
+                status,
+            });
+            Ok(())
+        }
+
+        /// Internal function to check that the function can be called in the status of the loan
+        fn _ensure_status(loan_info: &LoanInfo, allowed: &[LoanStatus]) -> Result<(), LoanError> {
+            if !allowed.contains(&loan_info.status) {
+                return Err(LoanError::InvalidLoanStatus(loan_info.status));
+            }
+            Ok(())
+        }
+
+        /// Internal function to give the collateral of a loan back to the borrower
+        fn _release_collateral(&self, loan_id: Id, loan_info: &LoanInfo) -> Result<(), LoanError> {
+            if self.unescrowed_collateral.contains(loan_id) {
+                return Ok(());
+            }
+            self._hand_over_collateral(loan_id, loan_info, loan_info.borrower)?;
+            Self::env().emit_event(CollateralReleased {
+                loan_id,
+                borrower: loan_info.borrower,
+                lender: loan_info.lender,
+                collection_id: loan_info.collection_id,
+                item_id: loan_info.item_id,
+            });
+            Ok(())
+        }
+
+        /// Internal function to transfer the collateral of a loan out of the escrow, the collateral
+        /// of migrated loans of version 0 has never been in escrow and stays where it is
+        fn _hand_over_collateral(
+            &self,
+            loan_id: Id,
+            loan_info: &LoanInfo,
+            to: AccountId,
+        ) -> Result<(), LoanError> {
+            if self.unescrowed_collateral.contains(loan_id) {
+                return Ok(());
+            }
+            let nft_contract = self
+                .collections
+                .get(loan_info.collection_id)
+                .ok_or(LoanError::UnknownCollection)?;
+            Self::_transfer_collateral(nft_contract, to, loan_info.item_id)
+        }
+
+        /// Internal function to transfer a collateral nft with the PSP34 contract of its collection
+        fn _transfer_collateral(
+            nft_contract: AccountId,
+            to: AccountId,
+            item_id: u32,
+        ) -> Result<(), LoanError> {
+            use ink::{env::CallFlags, prelude::vec::Vec};
+            use openbrush::contracts::psp34::{Id, PSP34Ref};
+
+            // The collection may check whether the receiving contract accepts the nft
+            let params =
+                PSP34Ref::transfer_builder(&nft_contract, to, Id::U32(item_id), Vec::new())
+                    .call_flags(CallFlags::default().set_allow_reentry(true))
+                    .params();
+            match Self::_invoke(params) {
+                Ok(Ok(Ok(()))) => Ok(()),
+                _ => Err(LoanError::CollateralTransferFailed),
+            }
+        }
+
+        /// Internal function to query the owner of a collateral nft
+        fn _collateral_owner(nft_contract: AccountId, item_id: u32) -> Option<AccountId> {
+            use openbrush::contracts::psp34::{Id, PSP34Ref};
+
+            let params = PSP34Ref::owner_of_builder(&nft_contract, Id::U32(item_id)).params();
+            Self::_invoke(params).ok().and_then(Result::ok).flatten()
+        }
+
+        /// Internal function to transfer PSP22 tokens of the contract
+        fn _transfer_psp22(
+            token: AccountId,
+            to: AccountId,
+            amount: Balance,
+        ) -> Result<(), LoanError> {
+            use openbrush::contracts::psp22::PSP22Ref;
+
+            let params = PSP22Ref::transfer_builder(&token, to, amount, Vec::new()).params();
+            match Self::_invoke(params) {
+                Ok(Ok(Ok(()))) => Ok(()),
+                _ => Err(LoanError::AssetTransferFailed),
+            }
+        }
+
+        /// Internal function to transfer PSP22 tokens the contract has been approved for
+        fn _transfer_psp22_from(
+            token: AccountId,
+            from: AccountId,
+            to: AccountId,
+            amount: Balance,
+        ) -> Result<(), LoanError> {
+            use openbrush::contracts::psp22::PSP22Ref;
+
+            let params =
+                PSP22Ref::transfer_from_builder(&token, from, to, amount, Vec::new()).params();
+            match Self::_invoke(params) {
+                Ok(Ok(Ok(()))) => Ok(()),
+                _ => Err(LoanError::AssetTransferFailed),
+            }
+        }
+
+        /// Internal function to perform a call to another contract
+        #[cfg(not(test))]
+        fn _invoke<Args: scale::Encode, R: scale::Decode>(
+            params: CallParams<DefaultEnvironment, Call<DefaultEnvironment>, Args, R>,
+        ) -> Result<ink::MessageResult<R>, ink::env::Error> {
+            params.try_invoke()
+        }
+
+        /// The off-chain environment can't call other contracts, so the encoded call is
+        /// dispatched to the mock contracts instead
+        #[cfg(test)]
+        fn _invoke<Args: scale::Encode, R: scale::Decode>(
+            params: CallParams<DefaultEnvironment, Call<DefaultEnvironment>, Args, R>,
+        ) -> Result<ink::MessageResult<R>, ink::env::Error> {
+            let input = scale::Encode::encode(params.exec_input());
+            let output =
+                crate::mock_contracts::call(*params.callee(), Self::env().account_id(), &input)?;
+            scale::Decode::decode(&mut &output[..]).map_err(Into::into)
+        }
+
+        /// Internal function to convert an amount of whole units into the balance of the chain
+        fn _to_planck(&self, amount: Balance) -> Result<Balance, LoanError> {
+            amount
+                .checked_mul(10u128.pow(self.decimals.get_or_default() as u32))
+                .ok_or(LoanError::Overflow)
+        }
+
+        /// Internal function to check that enough value has been transferred for an amount
+        /// Returns the transferred value above the amount, which is credited to the caller
+        /// Loans in another asset are paid separately, so all transferred value is credited
+        fn _excess_value(&self, asset: &LoanAsset, amount: Balance) -> Result<Balance, LoanError> {
+            if *asset != LoanAsset::Native {
+                return Ok(Self::env().transferred_value());
+            }
+            Self::env()
+                .transferred_value()
+                .checked_sub(self._to_planck(amount)?)
+                .ok_or(LoanError::NotEnoughFundsProvided)
+        }
+
+        /// Internal function to take an amount of the asset of a loan from the payer
+        /// The native currency has already been transferred with the call
+        fn _receive_asset(
+            &self,
+            asset: &LoanAsset,
+            from: AccountId,
+            amount: Balance,
+        ) -> Result<(), LoanError> {
+            let contract = Self::env().account_id();
+            match *asset {
+                LoanAsset::Native => Ok(()),
+                LoanAsset::Psp22(token) => {
+                    Self::_transfer_psp22_from(token, from, contract, amount)
+                }
+                LoanAsset::PalletAsset(id) => self._call_assets(AssetsCall::TransferApproved {
+                    id,
+                    owner: from,
+                    destination: contract,
+                    amount,
+                }),
+            }
+        }
+
+        /// Internal function to send an amount of the asset of a loan from the contract
+        fn _send_asset(
+            &self,
+            asset: &LoanAsset,
+            to: AccountId,
+            amount: Balance,
+        ) -> Result<(), LoanError> {
+            match *asset {
+                LoanAsset::Native => {
+                    <Self as DefaultEnv>::env().transfer(to, self._to_planck(amount)?)?;
+                    Ok(())
+                }
+                LoanAsset::Psp22(token) => Self::_transfer_psp22(token, to, amount),
+                LoanAsset::PalletAsset(id) => self._call_assets(AssetsCall::Transfer {
+                    id,
+                    target: to,
+                    amount,
+                }),
+            }
+        }
+
+        /// Internal function to add value to the credit of an account
+        fn _add_credit(&mut self, account: AccountId, amount: Balance) -> Result<(), LoanError> {
+            if amount == 0 {
+                return Ok(());
+            }
+            let credit = self
+                .get_credit(account)
+                .checked_add(amount)
+                .ok_or(LoanError::Overflow)?;
+            let total_credit = self
+                .total_credit
+                .get_or_default()
+                .checked_add(amount)
+                .ok_or(LoanError::Overflow)?;
+            self.total_credit.set(&total_credit);
+            self.credits.insert(account, &credit);
+            Self::env().emit_event(CreditAdded {
+                account,
+                amount,
+                credit,
+            });
+            Ok(())
+        }
+
+        /// Internal function to check that the caller has been granted the role
+        fn _ensure_role(&self, role: RoleType) -> Result<(), LoanError> {
+            self._ensure_root_migrated()?;
+            if !AccessControl::has_role(self, role, Some(Self::env().caller())) {
+                return Err(LoanError::MissingRole(role));
+            }
+            Ok(())
+        }
+
+        /// Internal function to check that the caller is the owner of the contract
+        fn _ensure_owner(&self) -> Result<(), LoanError> {
+            if Ownable::owner(self) != Some(Self::env().caller()) {
+                return Err(LoanError::CallerIsNotOwner);
+            }
+            Ok(())
+        }
+
+        /// Internal function to check that the root of an older storage version has been
+        /// migrated, a message that changes the storage would overwrite its settings otherwise
+        fn _ensure_root_migrated(&self) -> Result<(), LoanError> {
+            if self.storage_version.get().is_none() {
+                return Err(LoanError::MigrationPending);
+            }
+            Ok(())
+        }
+
+        /// Internal function to return the account of the community-loan-pool
+        fn _pallet_id(&self) -> Result<AccountId, LoanError> {
+            self.pallet_id.get().ok_or(LoanError::MigrationPending)
+        }
+
+        /// Internal function to return the id of a new loan and to increase it in the storage
+        fn _get_next_loan_id_and_increase(&mut self) -> Result<u32, LoanError> {
+            let loan_id = self
+                .last_loan_id
+                .get_or_default()
+                .checked_add(1)
+                .ok_or(LoanError::Overflow)?;
+            self.last_loan_id.set(&loan_id);
+            Ok(loan_id)
+        }
+
+        /// Internal function to charge the interest accrued since the last accrual to the loan
+        ///
+        /// Interest is simple interest on the outstanding principal between two accruals, so
+        /// charged interest, fees and penalties don't bear interest themselves. The charged
+        /// interest is rounded down to a whole unit and the fraction below one unit is carried
+        /// over to the next accrual, so the total interest doesn't depend on how often the loan
+        /// is touched through rounding.
+        /// Returns the interest that has been charged.
+        fn _accrue_interest(loan_info: &mut LoanInfo) -> Result<Balance, LoanError> {
+            let now = <Self as DefaultEnv>::env().block_timestamp();
+            let (interest, remainder) = Self::_calculate_interest(
+                loan_info.debt.principal,
+                loan_info.interest_rate,
+                now.saturating_sub(loan_info.timestamp),
+                loan_info.interest_remainder,
+            )?;
+            loan_info.borrowed_amount = loan_info
+                .borrowed_amount
+                .checked_add(interest)
+                .ok_or(LoanError::Overflow)?;
+            loan_info.debt.add(PaymentComponent::Interest, interest)?;
+            loan_info.interest_remainder = remainder;
+            loan_info.timestamp = now;
+            Ok(interest)
+        }
+
+        /// Internal function to calculate the simple interest on an amount over a period
+        /// Returns the interest rounded down to a whole unit and the fraction below one unit
+        /// scaled by `INTEREST_PRECISION`, after adding the fraction carried over before
+        fn _calculate_interest(
+            amount: Balance,
+            interest_rate: u32,
+            elapsed: Timestamp,
+            carried: Balance,
+        ) -> Result<(Balance, Balance), LoanError> {
+            // A u32 rate times a u64 period always fits into u128
+            let rate_time = interest_rate as Balance * elapsed as Balance;
+            // Split the amount to keep the intermediate products within u128
+            let whole_interest = (amount / INTEREST_PRECISION)
+                .checked_mul(rate_time)
+                .ok_or(LoanError::Overflow)?;
+            let scaled_interest = (amount % INTEREST_PRECISION)
+                .checked_mul(rate_time)
+                .and_then(|interest| interest.checked_add(carried))
+                .ok_or(LoanError::Overflow)?;
+            let interest = whole_interest
+                .checked_add(scaled_interest / INTEREST_PRECISION)
+                .ok_or(LoanError::Overflow)?;
+            Ok((interest, scaled_interest % INTEREST_PRECISION))
+        }
+
+        /// Internal function to calculate the due dates of the installments of a loan
+        /// Installments are due every payment period after the start, the last one at maturity
+        fn _due_dates(start: Timestamp, terms: &LoanTerms) -> Result<Vec<Timestamp>, LoanError> {
+            if terms.maturity <= start {
+                return Err(LoanError::InvalidLoanTerms);
+            }
+            if terms.repayment_type == RepaymentType::Balloon {
+                return Ok(vec![terms.maturity]);
+            }
+            if terms.payment_frequency == 0
+                || (terms.maturity - start - 1) / terms.payment_frequency
+                    >= MAX_INSTALLMENTS as Timestamp
+            {
+                return Err(LoanError::InvalidLoanTerms);
+            }
+            let mut due_dates = Vec::new();
+            let mut due_date = start.saturating_add(terms.payment_frequency);
+            while due_date < terms.maturity {
+                due_dates.push(due_date);
+                due_date = due_date.saturating_add(terms.payment_frequency);
+            }
+            due_dates.push(terms.maturity);
+            Ok(due_dates)
+        }
+
+        /// Internal function to split the principal over installments with the given due dates
+        /// The interest of every installment accrues on the principal that is still outstanding
+        /// since the previous due date, or since `period_start` for the first installment
+        fn _build_installments(
+            due_dates: &[Timestamp],
+            period_start: Timestamp,
+            principal: Balance,
+            interest_rate: u32,
+            repayment_type: RepaymentType,
+        ) -> Result<Vec<Installment>, LoanError> {
+            let count = due_dates.len() as Balance;
+            let mut outstanding = principal;
+            let mut previous_due_date = period_start;
+            due_dates
+                .iter()
+                .enumerate()
+                .map(|(index, &due_date)| {
+                    let (interest, _) = Self::_calculate_interest(
+                        outstanding,
+                        interest_rate,
+                        due_date.saturating_sub(previous_due_date),
+                        0,
+                    )?;
+                    let principal_due = if index + 1 == due_dates.len() {
+                        outstanding
+                    } else if repayment_type == RepaymentType::Amortizing {
+                        principal / count
+                    } else {
+                        0
+                    };
+                    outstanding -= principal_due;
+                    previous_due_date = due_date;
+                    Ok(Installment {
+                        due_date,
+                        principal: principal_due,
+                        interest,
+                        paid: 0,
+                        status: InstallmentStatus::Pending,
+                    })
+                })
+                .collect()
+        }
+
+        /// Internal function to spread drawn principal over the installments not due yet
+        /// The installments are recalculated, but keep what has already been paid for them.
+        /// Interest on the drawn principal accrues from the time it is drawn.
+        fn _reschedule(
+            &mut self,
+            loan_id: Id,
+            loan_info: &LoanInfo,
+            drawn_principal: Balance,
+        ) -> Result<(), LoanError> {
+            let mut schedule = self.schedules.get(loan_id).unwrap_or_default();
+            let first_future = schedule
+                .iter()
+                .position(|installment| installment.due_date > loan_info.timestamp)
+                .ok_or(LoanError::LoanMatured)?;
+            let period_start = match first_future {
+                0 => loan_info.start_date,
+                index => schedule[index - 1].due_date,
+            };
+            let future = schedule.split_off(first_future);
+            let principal = future
+                .iter()
+                .try_fold(0, |principal: Balance, installment| {
+                    principal.checked_add(installment.principal)
+                })
+                .ok_or(LoanError::Overflow)?;
+            let due_dates: Vec<_> = future
+                .iter()
+                .map(|installment| installment.due_date)
+                .collect();
+            let mut rescheduled = Self::_build_installments(
+                &due_dates,
+                period_start,
+                principal,
+                loan_info.interest_rate,
+                loan_info.repayment_type,
+            )?;
+            let drawn = Self::_build_installments(
+                &due_dates,
+                loan_info.timestamp,
+                drawn_principal,
+                loan_info.interest_rate,
+                loan_info.repayment_type,
+            )?;
+            for ((installment, drawn), previous) in rescheduled.iter_mut().zip(drawn).zip(future) {
+                installment.principal = installment
+                    .principal
+                    .checked_add(drawn.principal)
+                    .ok_or(LoanError::Overflow)?;
+                installment.interest = installment
+                    .interest
+                    .checked_add(drawn.interest)
+                    .ok_or(LoanError::Overflow)?;
+                installment.paid = previous.paid;
+                if installment.amount_due() == 0 {
+                    installment.status = InstallmentStatus::Paid;
+                }
+            }
+            schedule.append(&mut rescheduled);
+            self.schedules.insert(loan_id, &schedule);
+            Ok(())
+        }
+
+        /// Internal function to mark the installments whose due date has passed unpaid as missed
+        /// Installments nothing was owed for by their due date count as paid
+        fn _mark_missed_installments(schedule: &mut [Installment], now: Timestamp) {
+            for installment in schedule.iter_mut() {
+                if installment.status == InstallmentStatus::Pending && installment.due_date < now {
+                    installment.status = if installment.amount_due() == 0 {
+                        InstallmentStatus::Paid
+                    } else {
+                        InstallmentStatus::Missed
+                    };
+                }
+            }
+        }
+
+        /// Internal function to apply a repayment to the installments in the order they are due
+        fn _apply_payment(schedule: &mut [Installment], mut amount: Balance, now: Timestamp) {
+            for installment in schedule.iter_mut() {
+                if amount == 0 {
+                    break;
+                }
+                let payment = amount.min(installment.amount_due());
+                if payment == 0 {
+                    continue;
+                }
+                installment.paid += payment;
+                amount -= payment;
+                if installment.amount_due() == 0 {
+                    installment.status = if installment.due_date < now {
+                        InstallmentStatus::Late
+                    } else {
+                        InstallmentStatus::Paid
+                    };
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use ink::env::pay_with_call;
+        use ink::env::test::*;
+        use ink::storage::traits::Storable;
+        use proptest::prelude::*;
+        use scale::Encode;
+
+        fn create_contract() -> LoanContract {
+            let accounts = default_accounts();
+            set_sender(accounts.alice);
+            set_balance(contract_id(), 1000);
+            let mut loan = LoanContract::new(accounts.frank, 0);
+            assert_eq!(loan.register_collection(0, nft_contract()), Ok(()));
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, LOAN_ORIGINATOR, Some(accounts.alice)),
+                Ok(())
+            );
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, INTEREST_KEEPER, Some(accounts.alice)),
+                Ok(())
+            );
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, ORACLE, Some(accounts.alice)),
+                Ok(())
+            );
+            loan
+        }
+
+        fn contract_id() -> AccountId {
+            ink::env::test::callee::<ink::env::DefaultEnvironment>()
+        }
+        fn terms(interest_rate: u32) -> LoanTerms {
+            LoanTerms {
+                interest_rate,
+                maturity: 10 * MILLISECONDS_PER_YEAR,
+                payment_frequency: MILLISECONDS_PER_YEAR,
+                repayment_type: RepaymentType::InterestOnly,
+            }
+        }
+        fn nft_contract() -> AccountId {
+            AccountId::from([0x42; 32])
+        }
+        /// Mints the nft to the owner, approves the contract for it and has the oracle value it
+        fn mint_collateral(loan: &mut LoanContract, owner: AccountId, item_id: u32) {
+            crate::mock_nft::mint(nft_contract(), item_id, owner);
+            crate::mock_nft::approve(nft_contract(), item_id, contract_id());
+            let caller = ink::env::caller::<ink::env::DefaultEnvironment>();
+            set_sender(default_accounts().alice);
+            assert_eq!(loan.update_valuation(0, item_id, 2000), Ok(()));
+            set_sender(caller);
+        }
+        fn default_accounts() -> ink::env::test::DefaultAccounts<ink::env::DefaultEnvironment> {
+            ink::env::test::default_accounts::<ink::env::DefaultEnvironment>()
+        }
+        fn set_sender(sender: AccountId) {
+            ink::env::test::set_caller::<ink::env::DefaultEnvironment>(sender);
+        }
+        fn set_balance(account_id: AccountId, balance: Balance) {
+            ink::env::test::set_account_balance::<ink::env::DefaultEnvironment>(account_id, balance)
+        }
+        fn set_timestamp(timestamp: Timestamp) {
+            ink::env::test::set_block_timestamp::<ink::env::DefaultEnvironment>(timestamp)
+        }
+
+        type Event = <LoanContract as ::ink::reflect::ContractEventBase>::Type;
+
+        fn last_event() -> Event {
+            last_events(1).pop().expect("no event has been emitted")
+        }
+
+        fn last_events(count: usize) -> Vec<Event> {
+            let events: Vec<_> = recorded_events().collect();
+            events[events.len().saturating_sub(count)..]
+                .iter()
+                .map(|event| {
+                    <Event as scale::Decode>::decode(&mut &event.data[..])
+                        .expect("invalid event data")
+                })
+                .collect()
+        }
+
+        #[ink::test]
+        fn create_loan_works() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Ok(()));
+        }
+
+        #[ink::test]
+        fn create_loan_fails_if_not_enough_funds_transferred() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                900
+            );
+            assert_eq!(result, Err(LoanError::NotEnoughFundsProvided));
+        }
+
+        #[ink::test]
+        fn increase_loan_id_works() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Ok(()));
+            loan.get_loan_info(1);
+            mint_collateral(&mut loan, accounts.bob, 1);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 1, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Ok(()));
+            loan.get_loan_info(2);
+        }
+
+        #[ink::test]
+        fn withdraw_works() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Ok(()));
+            let contract_balance_before = ink::env::balance::<ink::env::DefaultEnvironment>();
+            let bob_balance_before =
+                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(Ok(1000), bob_balance_before);
+            assert_eq!(2000, contract_balance_before);
+            set_sender(accounts.bob);
+            let result = loan.withdraw_funds(1, 500);
+            assert_eq!(Ok(()), result);
+            let contract_balance_after = ink::env::balance::<ink::env::DefaultEnvironment>();
+            let bob_balance_after =
+                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(Ok(1500), bob_balance_after);
+            assert_eq!(1500, contract_balance_after);
+            let loan_info = loan.get_loan_info(1);
+            assert_eq!(500, loan_info.borrowed_amount);
+            assert_eq!(500, loan_info.available_amount);
+            let result = loan.withdraw_funds(1, 500);
+            assert_eq!(Ok(()), result);
+            let loan_info = loan.get_loan_info(1);
+            assert_eq!(1000, loan_info.borrowed_amount);
+            assert_eq!(0, loan_info.available_amount);
+        }
+
+        #[ink::test]
+        fn withdraw_fails_non_existing_loanid() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Ok(()));
+            let contract_balance_before = ink::env::balance::<ink::env::DefaultEnvironment>();
+            let bob_balance_before =
+                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(Ok(1000), bob_balance_before);
+            assert_eq!(2000, contract_balance_before);
+            set_sender(accounts.bob);
+            let result = loan.withdraw_funds(3, 500);
+            assert_eq!(Err(LoanError::NonExistingLoanId), result);
+        }
+
+        #[ink::test]
+        fn withdraw_fails_if_someone_but_the_borrower_calls() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.alice, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.bob, accounts.alice, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            let result = loan.withdraw_funds(1, 500);
+            assert_eq!(Err(LoanError::NotTheBorrower), result);
+            set_sender(accounts.charlie);
+            let result = loan.withdraw_funds(1, 500);
+            assert_eq!(Err(LoanError::NotTheBorrower), result);
+            let contract_balance_after = ink::env::balance::<ink::env::DefaultEnvironment>();
+            assert_eq!(2000, contract_balance_after);
+        }
+
+        #[ink::test]
+        fn withdraw_fails_insufficient_funds() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.alice, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.bob, accounts.alice, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            let result = loan.withdraw_funds(1, 1500);
+            assert_eq!(Err(LoanError::InsufficientLoanBalance), result);
+        }
+
+        #[ink::test]
+        fn repay_fails_if_amount_is_zero() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Ok(()));
+            let contract_balance_before = ink::env::balance::<ink::env::DefaultEnvironment>();
+            let bob_balance_before =
+                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(Ok(1000), bob_balance_before);
+            assert_eq!(2000, contract_balance_before);
+            set_sender(accounts.bob);
+            let result = loan.withdraw_funds(1, 500);
+            assert_eq!(Ok(()), result);
+            let repay_result = pay_with_call!(loan.repay(1, 0), 0);
+            assert_eq!(
+                Err(LoanError::RepayAmountMustBeHigherThanZero),
+                repay_result
+            );
+        }
+
+        #[ink::test]
+        fn repay_fails_not_enough_funds_provided() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Ok(()));
+            let contract_balance_before = ink::env::balance::<ink::env::DefaultEnvironment>();
+            let bob_balance_before =
+                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob);
+            assert_eq!(Ok(1000), bob_balance_before);
+            assert_eq!(2000, contract_balance_before);
+            set_sender(accounts.bob);
+            let result = loan.withdraw_funds(1, 500);
+            assert_eq!(Ok(()), result);
+            let repay_result = pay_with_call!(loan.repay(1, 500), 400);
+            assert_eq!(Err(LoanError::NotEnoughFundsProvided), repay_result);
+        }
+
+        #[ink::test]
+        fn charge_apy_works() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(1000)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 500));
+            let loan_info_before = loan.get_loan_info(1);
+            assert_eq!(500, loan_info_before.borrowed_amount);
+            set_sender(accounts.alice);
+            set_timestamp(MILLISECONDS_PER_YEAR);
+            let result = loan.charge_apy(1);
+            assert_eq!(Ok(()), result);
+            let loan_info_after = loan.get_loan_info(1);
+            assert_eq!(550, loan_info_after.borrowed_amount);
+            assert_eq!(MILLISECONDS_PER_YEAR, loan_info_after.timestamp);
+        }
+
+        #[ink::test]
+        fn charge_apy_without_borrowed_amount_charges_nothing() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(1000)),
+                1000
+            )
+            .unwrap();
+            set_timestamp(MILLISECONDS_PER_YEAR);
+            assert_eq!(Ok(()), loan.charge_apy(1));
+            assert_eq!(0, loan.get_loan_info(1).borrowed_amount);
+        }
+
+        #[ink::test]
+        fn interest_accrues_over_multiple_years() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(500)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 1000));
+            set_sender(accounts.alice);
+            // The query includes the interest without charging it
+            set_timestamp(2 * MILLISECONDS_PER_YEAR);
+            assert_eq!(1100, loan.get_loan_info(1).borrowed_amount);
+            assert_eq!(Ok(()), loan.charge_apy(1));
+            // Charged interest doesn't bear interest itself
+            set_timestamp(3 * MILLISECONDS_PER_YEAR);
+            assert_eq!(Ok(()), loan.charge_apy(1));
+            assert_eq!(1150, loan.get_loan_info(1).borrowed_amount);
+            set_timestamp(5 * MILLISECONDS_PER_YEAR);
+            assert_eq!(1250, loan.get_loan_info(1).borrowed_amount);
+        }
+
+        #[ink::test]
+        fn interest_fraction_is_carried_over() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(1)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 1000));
+            set_sender(accounts.alice);
+            // 0.01% of 1000 is 0.1 per year, which is rounded down on every charge
+            for year in 1..10 {
+                set_timestamp(year * MILLISECONDS_PER_YEAR);
+                assert_eq!(Ok(()), loan.charge_apy(1));
+                assert_eq!(1000, loan.get_loan_info(1).borrowed_amount);
+            }
+            set_timestamp(10 * MILLISECONDS_PER_YEAR);
+            assert_eq!(Ok(()), loan.charge_apy(1));
+            let loan_info = loan.get_loan_info(1);
+            assert_eq!(1001, loan_info.borrowed_amount);
+            assert_eq!(0, loan_info.interest_remainder);
+        }
+
+        #[ink::test]
+        fn withdraw_accrues_interest_before_borrowing() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(1000)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 500));
+            set_timestamp(MILLISECONDS_PER_YEAR);
+            set_sender(accounts.alice);
+            assert_eq!(loan.update_valuation(0, 0, 2000), Ok(()));
+            set_sender(accounts.bob);
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 500));
+            // Only the first withdrawal was borrowed during the year
+            let loan_info = loan.get_loan_info(1);
+            assert_eq!(1050, loan_info.borrowed_amount);
+            assert_eq!(MILLISECONDS_PER_YEAR, loan_info.timestamp);
+        }
+
+        #[ink::test]
+        fn create_loan_fails_without_originator_role() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            set_sender(accounts.bob);
+            mint_collateral(&mut loan, accounts.charlie, 0);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.bob, accounts.charlie, 0, 0, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Err(LoanError::MissingRole(LOAN_ORIGINATOR)));
+        }
+
+        #[ink::test]
+        fn update_and_delete_loan_fail_without_originator_role() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.charlie, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.charlie);
+            assert_eq!(
+                loan.update_loan(1, 0),
+                Err(LoanError::MissingRole(LOAN_ORIGINATOR))
+            );
+            assert_eq!(
+                loan.delete_loan(1),
+                Err(LoanError::MissingRole(LOAN_ORIGINATOR))
+            );
+        }
+
+        #[ink::test]
+        fn charge_apy_fails_without_interest_keeper_role() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(1000)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.charge_apy(1),
+                Err(LoanError::MissingRole(INTEREST_KEEPER))
+            );
+        }
+
+        #[ink::test]
+        fn granted_role_can_be_revoked() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, LOAN_ORIGINATOR, Some(accounts.bob)),
+                Ok(())
+            );
+            set_sender(accounts.bob);
+            mint_collateral(&mut loan, accounts.charlie, 0);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.bob, accounts.charlie, 0, 0, 2000, 500, terms(0)),
+                500
+            );
+            assert_eq!(result, Ok(()));
+            set_sender(accounts.alice);
+            assert_eq!(
+                AccessControl::revoke_role(&mut loan, LOAN_ORIGINATOR, Some(accounts.bob)),
+                Ok(())
+            );
+            set_sender(accounts.bob);
+            mint_collateral(&mut loan, accounts.charlie, 1);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.bob, accounts.charlie, 0, 1, 2000, 500, terms(0)),
+                500
+            );
+            assert_eq!(result, Err(LoanError::MissingRole(LOAN_ORIGINATOR)));
+        }
+
+        #[ink::test]
+        fn only_admin_can_grant_roles() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            assert!(AccessControl::has_role(&loan, ADMIN, Some(accounts.alice)));
+            set_sender(accounts.bob);
+            assert!(
+                AccessControl::grant_role(&mut loan, LOAN_ORIGINATOR, Some(accounts.bob)).is_err()
+            );
+        }
+
+        #[ink::test]
+        fn repay_fails_if_someone_but_the_borrower_calls() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.charlie);
+            let repay_result = pay_with_call!(loan.repay(1, 500), 500);
+            assert_eq!(Err(LoanError::NotTheBorrower), repay_result);
+        }
+
+        #[ink::test]
+        fn create_loan_emits_event() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 2);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 2, 2000, 1000, terms(500)),
+                1000
+            )
+            .unwrap();
+            let raw_event = recorded_events().last().unwrap();
+            assert_eq!(4, raw_event.topics.len());
+            match last_event() {
+                Event::LoanCreated(event) => {
+                    assert_eq!(1, event.loan_id);
+                    assert_eq!(accounts.bob, event.borrower);
+                    assert_eq!(accounts.alice, event.lender);
+                    assert_eq!(0, event.collection_id);
+                    assert_eq!(2, event.item_id);
+                    assert_eq!(2000, event.collateral_price);
+                    assert_eq!(1000, event.available_amount);
+                    assert_eq!(500, event.interest_rate);
+                }
+                _ => panic!("expected LoanCreated event"),
+            }
+        }
+
+        #[ink::test]
+        fn update_loan_emits_event() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 500, terms(0)),
+                500
+            )
+            .unwrap();
+            assert_eq!(Ok(()), pay_with_call!(loan.update_loan(1, 300), 300));
+            match last_event() {
+                Event::LoanUpdated(event) => {
+                    assert_eq!(1, event.loan_id);
+                    assert_eq!(accounts.bob, event.borrower);
+                    assert_eq!(accounts.alice, event.lender);
+                    assert_eq!(300, event.additional_available_amount);
+                    assert_eq!(800, event.available_amount);
+                }
+                _ => panic!("expected LoanUpdated event"),
+            }
+        }
+
+        #[ink::test]
+        fn withdraw_emits_event() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 400));
+            match last_event() {
+                Event::FundsWithdrawn(event) => {
+                    assert_eq!(1, event.loan_id);
+                    assert_eq!(accounts.bob, event.borrower);
+                    assert_eq!(accounts.alice, event.lender);
+                    assert_eq!(400, event.amount);
+                    assert_eq!(400, event.borrowed_amount);
+                }
+                _ => panic!("expected FundsWithdrawn event"),
+            }
+        }
+
+        #[ink::test]
+        fn charge_apy_emits_event() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(1000)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 1000));
+            set_sender(accounts.alice);
+            set_timestamp(MILLISECONDS_PER_YEAR);
+            assert_eq!(Ok(()), loan.charge_apy(1));
+            match last_event() {
+                Event::InterestCharged(event) => {
+                    assert_eq!(1, event.loan_id);
+                    assert_eq!(accounts.bob, event.borrower);
+                    assert_eq!(accounts.alice, event.lender);
+                    assert_eq!(100, event.interest);
+                    assert_eq!(1100, event.borrowed_amount);
+                }
+                _ => panic!("expected InterestCharged event"),
+            }
+        }
+
+        #[ink::test]
+        fn failed_message_emits_no_event() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let events_before = recorded_events().count();
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                900
+            );
+            assert_eq!(result, Err(LoanError::NotEnoughFundsProvided));
+            assert_eq!(events_before, recorded_events().count());
+        }
+
+        #[ink::test]
+        fn amounts_are_scaled_by_decimals() {
+            let accounts = default_accounts();
+            set_sender(accounts.alice);
+            let mut loan = LoanContract::new(accounts.frank, 3);
+            AccessControl::grant_role(&mut loan, LOAN_ORIGINATOR, Some(accounts.alice)).unwrap();
+            AccessControl::grant_role(&mut loan, ORACLE, Some(accounts.alice)).unwrap();
+            loan.register_collection(0, nft_contract()).unwrap();
+            assert_eq!(3, loan.decimals());
+            mint_collateral(&mut loan, accounts.bob, 0);
+            assert_eq!(loan.update_valuation(0, 0, 4), Ok(()));
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 4, 2, terms(0)),
+                1999
+            );
+            assert_eq!(result, Err(LoanError::NotEnoughFundsProvided));
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 4, 2, terms(0)),
+                2000
+            );
+            assert_eq!(result, Ok(()));
+            set_sender(accounts.bob);
+            let bob_balance_before =
+                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob).unwrap();
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 1));
+            let bob_balance_after =
+                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob).unwrap();
+            assert_eq!(bob_balance_before + 1000, bob_balance_after);
+            let loan_info = loan.get_loan_info(1);
+            assert_eq!(1, loan_info.borrowed_amount);
+            assert_eq!(1, loan_info.available_amount);
+        }
+
+        #[ink::test]
+        fn create_loan_takes_collateral_into_escrow() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 7);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 7, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Ok(()));
+            assert_eq!(
+                Some(contract_id()),
+                crate::mock_nft::owner_of(nft_contract(), 7)
+            );
+            assert_eq!(LoanStatus::Pending, loan.get_loan_info(1).status);
+        }
+
+        #[ink::test]
+        fn create_loan_fails_for_unknown_collection() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 5, 0, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Err(LoanError::UnknownCollection));
+        }
+
+        #[ink::test]
+        fn create_loan_fails_if_borrower_does_not_own_collateral() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.charlie, 0);
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Err(LoanError::NotTheCollateralOwner));
+        }
+
+        #[ink::test]
+        fn create_loan_fails_if_collateral_is_not_approved() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            crate::mock_nft::mint(nft_contract(), 0, accounts.bob);
+            assert_eq!(loan.update_valuation(0, 0, 2000), Ok(()));
+            let result = pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            );
+            assert_eq!(result, Err(LoanError::CollateralTransferFailed));
+        }
+
+        #[ink::test]
+        fn create_loan_requires_a_current_valuation() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            crate::mock_nft::mint(nft_contract(), 0, accounts.bob);
+            crate::mock_nft::approve(nft_contract(), 0, contract_id());
+            let create = |loan: &mut LoanContract| {
+                pay_with_call!(
+                    loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                    1000
+                )
+            };
+            assert_eq!(create(&mut loan), Err(LoanError::StaleValuation));
+            assert_eq!(loan.update_valuation(0, 0, 1500), Ok(()));
+            set_timestamp(DEFAULT_MAX_VALUATION_AGE + 1);
+            assert_eq!(create(&mut loan), Err(LoanError::StaleValuation));
+            assert_eq!(loan.update_valuation(0, 0, 1500), Ok(()));
+            assert_eq!(create(&mut loan), Ok(()));
+            // The price given on creation isn't recorded as a valuation
+            assert_eq!(
+                vec![1500, 1500],
+                loan.get_valuation_history(0, 0, 0, 10)
+                    .iter()
+                    .map(|valuation| valuation.price)
+                    .collect::<Vec<_>>()
+            );
+        }
+
+        #[ink::test]
+        fn register_collection_fails_if_already_registered() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            assert_eq!(Some(nft_contract()), loan.get_collection(0));
+            assert_eq!(
+                loan.register_collection(0, accounts.django),
+                Err(LoanError::CollectionAlreadyRegistered)
+            );
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.register_collection(1, accounts.django),
+                Err(LoanError::MissingRole(ADMIN))
+            );
+        }
+
+        #[ink::test]
+        fn liquidate_fails_if_not_liquidatable() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            assert_eq!(loan.liquidate(1), Err(LoanError::MissingRole(LIQUIDATOR)));
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, LIQUIDATOR, Some(accounts.alice)),
+                Ok(())
+            );
+            assert_eq!(
+                loan.liquidate(1),
+                Err(LoanError::InvalidLoanStatus(LoanStatus::Pending))
+            );
+            set_sender(accounts.bob);
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 500));
+            set_sender(accounts.alice);
+            assert_eq!(loan.liquidate(1), Err(LoanError::NotLiquidatable));
+            assert_eq!(None, loan.get_liquidation(1));
+            assert_eq!(
+                Some(contract_id()),
+                crate::mock_nft::owner_of(nft_contract(), 0)
+            );
+        }
+
+        #[ink::test]
+        fn loan_is_liquidatable_when_defaulted_past_grace_period_or_above_max_ltv() {
+            let config = LiquidationConfig {
+                grace_period: 1000,
+                max_ltv: 8000,
+                ..Default::default()
+            };
+            let mut loan_info = LoanInfo {
+                collateral_price: 1000,
+                borrowed_amount: 800,
+                status: LoanStatus::Active,
+                ..Default::default()
+            };
+            let mut schedule =
+                LoanContract::_build_installments(&[5000], 0, 800, 0, RepaymentType::Balloon)
+                    .unwrap();
+            assert!(!LoanContract::_is_liquidatable(
+                &loan_info,
+                &schedule,
+                &config,
+                Some(1000),
+                6000
+            ));
+            assert!(LoanContract::_is_liquidatable(
+                &loan_info,
+                &schedule,
+                &config,
+                Some(1000),
+                6001
+            ));
+            LoanContract::_apply_payment(&mut schedule, 800, 6001);
+            assert!(!LoanContract::_is_liquidatable(
+                &loan_info,
+                &schedule,
+                &config,
+                Some(1000),
+                6001
+            ));
+            loan_info.borrowed_amount = 801;
+            assert!(LoanContract::_is_liquidatable(
+                &loan_info,
+                &schedule,
+                &config,
+                Some(1000),
+                0
+            ));
+            assert!(!LoanContract::_is_liquidatable(
+                &loan_info, &schedule, &config, None, 0
+            ));
+            loan_info.borrowed_amount = 0;
+            loan_info.status = LoanStatus::Defaulted;
+            assert!(LoanContract::_is_liquidatable(
+                &loan_info,
+                &schedule,
+                &config,
+                Some(1000),
+                0
+            ));
+        }
+
+        #[ink::test]
+        fn distribute_recovery_splits_proceeds() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.charlie, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, LIQUIDATOR, Some(accounts.alice)),
+                Ok(())
+            );
+            assert_eq!(
+                pay_with_call!(loan.distribute_recovery(1, 100), 100),
+                Err(LoanError::NotLiquidated)
+            );
+            let config = LiquidationConfig {
+                lender_share: 6000,
+                ..Default::default()
+            };
+            assert_eq!(loan.set_liquidation_config(config), Ok(()));
+            loan.liquidations.insert(
+                1,
+                &Liquidation {
+                    timestamp: 0,
+                    recipient: accounts.charlie,
+                    debt: 500,
+                    shortfall: 0,
+                    recovered: 0,
+                },
+            );
+            let lender_balance =
+                get_account_balance::<ink::env::DefaultEnvironment>(accounts.charlie);
+            assert_eq!(
+                pay_with_call!(loan.distribute_recovery(1, 300), 300),
+                Ok(())
+            );
+            assert_eq!(
+                Ok(lender_balance.unwrap() + 180),
+                get_account_balance::<ink::env::DefaultEnvironment>(accounts.charlie)
+            );
+            assert_eq!(200, loan.get_liquidation(1).unwrap().shortfall);
+            assert_eq!(
+                pay_with_call!(loan.distribute_recovery(1, 300), 300),
+                Ok(())
+            );
+            match last_event() {
+                Event::RecoveryDistributed(event) => {
+                    assert_eq!(1, event.loan_id);
+                    assert_eq!(120, event.lender_amount);
+                    assert_eq!(80, event.pool_amount);
+                    assert_eq!(100, event.borrower_amount);
+                    assert_eq!(0, event.shortfall);
+                }
+                _ => panic!("expected RecoveryDistributed event"),
+            }
+        }
+
+        #[ink::test]
+        fn set_liquidation_config_validates_config() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            let config = LiquidationConfig {
+                collateral_recipient: CollateralRecipient::CommunityLoanPool,
+                ..Default::default()
+            };
+            assert_eq!(loan.set_liquidation_config(config.clone()), Ok(()));
+            assert_eq!(config, loan.get_liquidation_config());
+            for invalid in [
+                LiquidationConfig {
+                    max_ltv: 0,
+                    ..Default::default()
+                },
+                LiquidationConfig {
+                    lender_share: BASIS_POINTS + 1,
+                    ..Default::default()
+                },
+            ] {
+                assert_eq!(
+                    loan.set_liquidation_config(invalid),
+                    Err(LoanError::InvalidLiquidationConfig)
+                );
+            }
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.set_liquidation_config(config),
+                Err(LoanError::MissingRole(ADMIN))
+            );
+        }
+
+        #[ink::test]
+        fn withdraw_activates_loan() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            assert_eq!(LoanStatus::Pending, loan.get_loan_info(1).status);
+            set_timestamp(100);
+            set_sender(accounts.bob);
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 500));
+            match &last_events(2)[0] {
+                Event::LoanStatusChanged(event) => {
+                    assert_eq!(1, event.loan_id);
+                    assert_eq!(LoanStatus::Pending, event.previous_status);
+                    assert_eq!(LoanStatus::Active, event.status);
+                }
+                _ => panic!("expected LoanStatusChanged event"),
+            }
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 500));
+            assert_eq!(LoanStatus::Active, loan.get_loan_info(1).status);
+            assert_eq!(
+                vec![
+                    StatusChange {
+                        status: LoanStatus::Pending,
+                        timestamp: 0
+                    },
+                    StatusChange {
+                        status: LoanStatus::Active,
+                        timestamp: 100
+                    },
+                ],
+                loan.get_status_history(1)
+            );
+        }
+
+        #[ink::test]
+        fn update_loan_status_follows_transitions() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            assert_eq!(
+                loan.update_loan_status(1, LoanStatus::Delinquent),
+                Err(LoanError::InvalidStatusTransition)
+            );
+            assert_eq!(
+                loan.update_loan_status(1, LoanStatus::Active),
+                Err(LoanError::InvalidStatusTransition)
+            );
+            set_sender(accounts.bob);
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 500));
+            assert_eq!(
+                loan.update_loan_status(1, LoanStatus::Delinquent),
+                Err(LoanError::MissingRole(ADMIN))
+            );
+            set_sender(accounts.alice);
+            assert_eq!(
+                loan.update_loan_status(1, LoanStatus::Repaid),
+                Err(LoanError::InvalidStatusTransition)
+            );
+            assert_eq!(loan.update_loan_status(1, LoanStatus::Delinquent), Ok(()));
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.withdraw_funds(1, 100),
+                Err(LoanError::InvalidLoanStatus(LoanStatus::Delinquent))
+            );
+            set_sender(accounts.alice);
+            assert_eq!(loan.update_loan_status(1, LoanStatus::Active), Ok(()));
+            assert_eq!(loan.update_loan_status(1, LoanStatus::Defaulted), Ok(()));
+            assert_eq!(
+                loan.update_loan_status(1, LoanStatus::Active),
+                Err(LoanError::InvalidStatusTransition)
+            );
+            assert_eq!(
+                pay_with_call!(loan.update_loan(1, 100), 100),
+                Err(LoanError::InvalidLoanStatus(LoanStatus::Defaulted))
+            );
+            let statuses: Vec<LoanStatus> = loan
+                .get_status_history(1)
+                .into_iter()
+                .map(|change| change.status)
+                .collect();
+            assert_eq!(
+                vec![
+                    LoanStatus::Pending,
+                    LoanStatus::Active,
+                    LoanStatus::Delinquent,
+                    LoanStatus::Active,
+                    LoanStatus::Defaulted,
+                ],
+                statuses
+            );
+        }
+
+        #[ink::test]
+        fn repay_fails_on_pending_loan() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(
+                pay_with_call!(loan.repay(1, 100), 100),
+                Err(LoanError::InvalidLoanStatus(LoanStatus::Pending))
+            );
+        }
+
+        fn schedule_terms(repayment_type: RepaymentType, years: u64) -> LoanTerms {
+            LoanTerms {
+                interest_rate: 1000,
+                maturity: years * MILLISECONDS_PER_YEAR,
+                payment_frequency: MILLISECONDS_PER_YEAR,
+                repayment_type,
+            }
+        }
+
+        fn amounts(schedule: &[Installment]) -> Vec<(Balance, Balance)> {
+            schedule
+                .iter()
+                .map(|installment| (installment.principal, installment.interest))
+                .collect()
+        }
+
+        #[ink::test]
+        fn amortizing_loan_pays_back_principal_in_equal_parts() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let terms = schedule_terms(RepaymentType::Amortizing, 4);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 1000), Ok(()));
+            let schedule = loan.get_schedule(1);
+            assert_eq!(
+                vec![(250, 100), (250, 75), (250, 50), (250, 25)],
+                amounts(&schedule)
+            );
+            assert_eq!(4 * MILLISECONDS_PER_YEAR, schedule[3].due_date);
+            assert_eq!(Some((MILLISECONDS_PER_YEAR, 350)), loan.next_amount_due(1));
+        }
+
+        #[ink::test]
+        fn interest_only_loan_pays_back_principal_at_maturity() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let terms = schedule_terms(RepaymentType::InterestOnly, 3);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 1000), Ok(()));
+            assert_eq!(
+                vec![(0, 100), (0, 100), (1000, 100)],
+                amounts(&loan.get_schedule(1))
+            );
+        }
+
+        #[ink::test]
+        fn balloon_loan_is_paid_back_at_maturity() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let terms = schedule_terms(RepaymentType::Balloon, 2);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 1000), Ok(()));
+            let schedule = loan.get_schedule(1);
+            assert_eq!(vec![(1000, 200)], amounts(&schedule));
+            assert_eq!(2 * MILLISECONDS_PER_YEAR, schedule[0].due_date);
+            assert_eq!(2 * MILLISECONDS_PER_YEAR, loan.get_loan_info(1).maturity);
+        }
+
+        #[ink::test]
+        fn create_loan_fails_with_invalid_terms() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            set_timestamp(MILLISECONDS_PER_YEAR);
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let matured = schedule_terms(RepaymentType::Balloon, 1);
+            let mut without_frequency = schedule_terms(RepaymentType::Amortizing, 2);
+            without_frequency.payment_frequency = 0;
+            let mut too_many_installments = schedule_terms(RepaymentType::Amortizing, 2);
+            too_many_installments.payment_frequency = 1000;
+            for terms in [matured, without_frequency, too_many_installments] {
+                assert_eq!(
+                    pay_with_call!(
+                        loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms),
+                        1000
+                    ),
+                    Err(LoanError::InvalidLoanTerms)
+                );
+            }
+        }
+
+        #[ink::test]
+        fn unpaid_installments_are_missed_after_due_date() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let terms = schedule_terms(RepaymentType::Amortizing, 4);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 1000), Ok(()));
+            set_timestamp(MILLISECONDS_PER_YEAR);
+            assert_eq!(InstallmentStatus::Pending, loan.get_schedule(1)[0].status);
+            set_timestamp(2 * MILLISECONDS_PER_YEAR + 1);
+            let statuses: Vec<_> = loan
+                .get_schedule(1)
+                .iter()
+                .map(|installment| installment.status)
+                .collect();
+            assert_eq!(
+                vec![
+                    InstallmentStatus::Missed,
+                    InstallmentStatus::Missed,
+                    InstallmentStatus::Pending,
+                    InstallmentStatus::Pending,
+                ],
+                statuses
+            );
+            assert_eq!(Some((MILLISECONDS_PER_YEAR, 675)), loan.next_amount_due(1));
+        }
+
+        #[ink::test]
+        fn payments_are_applied_to_installments_in_order() {
+            let mut schedule = LoanContract::_build_installments(
+                &[MILLISECONDS_PER_YEAR, 2 * MILLISECONDS_PER_YEAR],
+                0,
+                1000,
+                1000,
+                RepaymentType::Amortizing,
+            )
+            .unwrap();
+            LoanContract::_apply_payment(&mut schedule, 650, MILLISECONDS_PER_YEAR);
+            assert_eq!(InstallmentStatus::Paid, schedule[0].status);
+            assert_eq!((600, 50), (schedule[0].paid, schedule[1].paid));
+            LoanContract::_mark_missed_installments(&mut schedule, 3 * MILLISECONDS_PER_YEAR);
+            assert_eq!(InstallmentStatus::Missed, schedule[1].status);
+            LoanContract::_apply_payment(&mut schedule, 1000, 3 * MILLISECONDS_PER_YEAR);
+            assert_eq!(InstallmentStatus::Late, schedule[1].status);
+            assert_eq!(550, schedule[1].paid);
+        }
+
+        #[ink::test]
+        fn schedule_has_no_principal_before_funds_are_drawn() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let terms = schedule_terms(RepaymentType::Amortizing, 2);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms),
+                1000
+            )
+            .unwrap();
+            assert_eq!(pay_with_call!(loan.update_loan(1, 300), 300), Ok(()));
+            assert_eq!(vec![(0, 0), (0, 0)], amounts(&loan.get_schedule(1)));
+            assert_eq!(None, loan.next_amount_due(1));
+            set_timestamp(MILLISECONDS_PER_YEAR + 1);
+            assert_eq!(InstallmentStatus::Paid, loan.get_schedule(1)[0].status);
+        }
+
+        #[ink::test]
+        fn withdrawals_are_spread_over_the_future_installments() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let terms = schedule_terms(RepaymentType::Amortizing, 4);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 700), Ok(()));
+            assert_eq!(
+                vec![(175, 70), (175, 52), (175, 35), (175, 17)],
+                amounts(&loan.get_schedule(1))
+            );
+            set_timestamp(MILLISECONDS_PER_YEAR + 1);
+            set_sender(accounts.alice);
+            assert_eq!(loan.update_valuation(0, 0, 2000), Ok(()));
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 300), Ok(()));
+            // The drawn principal only accrues interest from the time of the withdrawal
+            assert_eq!(
+                vec![(175, 70), (275, 81), (275, 55), (275, 27)],
+                amounts(&loan.get_schedule(1))
+            );
+        }
+
+        #[ink::test]
+        fn withdraw_fails_after_maturity() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            set_timestamp(10 * MILLISECONDS_PER_YEAR);
+            assert_eq!(loan.withdraw_funds(1, 100), Err(LoanError::LoanMatured));
+        }
+
+        #[ink::test]
+        fn update_valuation_records_history() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.update_valuation(0, 0, 2500),
+                Err(LoanError::MissingRole(ORACLE))
+            );
+            set_sender(accounts.alice);
+            assert_eq!(
+                loan.update_valuation(1, 0, 2500),
+                Err(LoanError::UnknownCollection)
+            );
+            assert_eq!(
+                loan.update_valuation(0, 0, 0),
+                Err(LoanError::InvalidValuation)
+            );
+            set_timestamp(100);
+            assert_eq!(loan.update_valuation(0, 0, 2500), Ok(()));
+            assert_eq!(loan.update_valuation(0, 0, 2400), Ok(()));
+            match last_event() {
+                Event::ValuationUpdated(event) => {
+                    assert_eq!((0, 0), (event.collection_id, event.item_id));
+                    assert_eq!(2400, event.price);
+                    assert_eq!(100, event.timestamp);
+                }
+                _ => panic!("expected ValuationUpdated event"),
+            }
+            assert_eq!(
+                Some(Valuation {
+                    price: 2400,
+                    timestamp: 100
+                }),
+                loan.get_valuation(0, 0)
+            );
+            let prices: Vec<_> = loan
+                .get_valuation_history(0, 0, 0, 10)
+                .iter()
+                .map(|valuation| valuation.price)
+                .collect();
+            assert_eq!(vec![2000, 2500, 2400], prices);
+            assert_eq!(1, loan.get_valuation_history(0, 0, 1, 1).len());
+            assert!(loan.get_valuation_history(0, 0, 3, 10).is_empty());
+            assert_eq!(None, loan.get_valuation(0, 1));
+        }
+
+        #[ink::test]
+        fn collateral_value_fails_if_valuation_is_stale() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            set_timestamp(DEFAULT_MAX_VALUATION_AGE);
+            assert_eq!(Ok(2000), loan._collateral_value(0, 0));
+            set_timestamp(DEFAULT_MAX_VALUATION_AGE + 1);
+            assert_eq!(Err(LoanError::StaleValuation), loan._collateral_value(0, 0));
+            assert_eq!(
+                loan.set_max_valuation_age(2 * DEFAULT_MAX_VALUATION_AGE),
+                Ok(())
+            );
+            assert_eq!(Ok(2000), loan._collateral_value(0, 0));
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.set_max_valuation_age(0),
+                Err(LoanError::MissingRole(ADMIN))
+            );
+        }
+
+        #[ink::test]
+        fn create_loan_fails_above_max_ltv() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            assert_eq!(loan.update_valuation(0, 0, 1000), Ok(()));
+            assert_eq!(
+                pay_with_call!(
+                    loan.create_loan(accounts.alice, accounts.bob, 0, 0, 1000, 701, terms(0)),
+                    701
+                ),
+                Err(LoanError::LtvExceeded)
+            );
+            // The LTV is checked against the oracle, not the collateral price of the loan
+            assert_eq!(
+                pay_with_call!(
+                    loan.create_loan(accounts.alice, accounts.bob, 0, 0, 10_000, 701, terms(0)),
+                    701
+                ),
+                Err(LoanError::LtvExceeded)
+            );
+            assert_eq!(loan.set_collection_max_ltv(0, Some(8000)), Ok(()));
+            assert_eq!(8000, loan.get_max_ltv(0));
+            assert_eq!(DEFAULT_MAX_LTV, loan.get_max_ltv(1));
+            assert_eq!(
+                pay_with_call!(
+                    loan.create_loan(accounts.alice, accounts.bob, 0, 0, 1000, 701, terms(0)),
+                    701
+                ),
+                Ok(())
+            );
+        }
+
+        #[ink::test]
+        fn update_loan_and_withdraw_fail_above_max_ltv() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            assert_eq!(loan.update_valuation(0, 0, 1000), Ok(()));
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 1000, 500, terms(0)),
+                500
+            )
+            .unwrap();
+            assert_eq!(
+                pay_with_call!(loan.update_loan(1, 201), 201),
+                Err(LoanError::LtvExceeded)
+            );
+            assert_eq!(pay_with_call!(loan.update_loan(1, 200), 200), Ok(()));
+            // The collateral lost value after the loan has been funded
+            assert_eq!(loan.update_valuation(0, 0, 500), Ok(()));
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 351), Err(LoanError::LtvExceeded));
+            assert_eq!(loan.withdraw_funds(1, 350), Ok(()));
+            assert_eq!(Ok(7000), loan.current_ltv(1));
+            set_timestamp(DEFAULT_MAX_VALUATION_AGE + 1);
+            assert_eq!(loan.withdraw_funds(1, 1), Err(LoanError::StaleValuation));
+            assert_eq!(Err(LoanError::StaleValuation), loan.current_ltv(1));
+            assert_eq!(Err(LoanError::NonExistingLoanId), loan.current_ltv(2));
+        }
+
+        #[ink::test]
+        fn set_max_ltv_validates_ratio() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            assert_eq!(loan.set_max_ltv(0), Err(LoanError::InvalidLtv));
+            assert_eq!(
+                loan.set_max_ltv(BASIS_POINTS + 1),
+                Err(LoanError::InvalidLtv)
+            );
+            assert_eq!(
+                loan.set_collection_max_ltv(0, Some(0)),
+                Err(LoanError::InvalidLtv)
+            );
+            assert_eq!(loan.set_max_ltv(5000), Ok(()));
+            assert_eq!(loan.set_collection_max_ltv(0, Some(6000)), Ok(()));
+            assert_eq!(loan.set_collection_max_ltv(0, None), Ok(()));
+            assert_eq!(5000, loan.get_max_ltv(0));
+            set_sender(accounts.bob);
+            assert_eq!(loan.set_max_ltv(6000), Err(LoanError::MissingRole(ADMIN)));
+        }
+
+        #[ink::test]
+        fn debt_is_split_into_principal_interest_and_charges() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(1000)),
+                1000
+            )
+            .unwrap();
+            assert_eq!(
+                loan.add_charge(1, Charge::Fee, 10),
+                Err(LoanError::InvalidLoanStatus(LoanStatus::Pending))
+            );
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 400), Ok(()));
+            assert_eq!(
+                loan.add_charge(1, Charge::Fee, 10),
+                Err(LoanError::MissingRole(INTEREST_KEEPER))
+            );
+            set_timestamp(MILLISECONDS_PER_YEAR);
+            set_sender(accounts.alice);
+            assert_eq!(loan.charge_apy(1), Ok(()));
+            assert_eq!(
+                loan.add_charge(1, Charge::Fee, 0),
+                Err(LoanError::ChargeMustBeHigherThanZero)
+            );
+            assert_eq!(loan.add_charge(1, Charge::Fee, 10), Ok(()));
+            assert_eq!(loan.add_charge(1, Charge::Penalty, 5), Ok(()));
+            match last_event() {
+                Event::ChargeAdded(event) => {
+                    assert_eq!(Charge::Penalty, event.charge);
+                    assert_eq!(5, event.amount);
+                    assert_eq!(455, event.borrowed_amount);
+                }
+                _ => panic!("expected ChargeAdded event"),
+            }
+            let loan_info = loan.get_loan_info(1);
+            assert_eq!(
+                Debt {
+                    principal: 400,
+                    interest: 40,
+                    fees: 10,
+                    penalties: 5,
+                },
+                loan_info.debt
+            );
+            assert_eq!(455, loan_info.borrowed_amount);
+            assert_eq!(
+                455,
+                loan.portfolio_stats(LoanAsset::Native).total_outstanding
+            );
+            // Only the principal bears interest
+            set_timestamp(2 * MILLISECONDS_PER_YEAR);
+            assert_eq!(loan.charge_apy(1), Ok(()));
+            assert_eq!(
+                Debt {
+                    principal: 400,
+                    interest: 80,
+                    fees: 10,
+                    penalties: 5,
+                },
+                loan.get_loan_info(1).debt
+            );
+        }
+
+        #[ink::test]
+        fn payments_follow_the_waterfall() {
+            let debt = Debt {
+                principal: 400,
+                interest: 40,
+                fees: 10,
+                penalties: 5,
+            };
+            let mut remaining = debt.clone();
+            let (paid, left) = remaining.pay(30, &DEFAULT_PAYMENT_WATERFALL);
+            assert_eq!(0, left);
+            assert_eq!(
+                Debt {
+                    principal: 0,
+                    interest: 15,
+                    fees: 10,
+                    penalties: 5,
+                },
+                paid
+            );
+            assert_eq!(25, remaining.interest);
+            let mut remaining = debt;
+            let principal_first = [
+                PaymentComponent::Principal,
+                PaymentComponent::Interest,
+                PaymentComponent::Penalties,
+                PaymentComponent::Fees,
+            ];
+            let (paid, left) = remaining.pay(500, &principal_first);
+            assert_eq!(45, left);
+            assert_eq!(Debt::default(), remaining);
+            assert_eq!(400, paid.part(PaymentComponent::Principal));
+            assert_eq!(10, paid.part(PaymentComponent::Fees));
+        }
+
+        #[ink::test]
+        fn set_payment_waterfall_validates_order() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            assert_eq!(
+                DEFAULT_PAYMENT_WATERFALL.to_vec(),
+                loan.get_payment_waterfall()
+            );
+            let interest_first = vec![
+                PaymentComponent::Interest,
+                PaymentComponent::Fees,
+                PaymentComponent::Penalties,
+                PaymentComponent::Principal,
+            ];
+            for waterfall in [
+                vec![],
+                interest_first[..3].to_vec(),
+                vec![PaymentComponent::Interest; 4],
+                [interest_first.clone(), vec![PaymentComponent::Fees]].concat(),
+            ] {
+                assert_eq!(
+                    loan.set_payment_waterfall(waterfall),
+                    Err(LoanError::InvalidWaterfall)
+                );
+            }
+            assert_eq!(loan.set_payment_waterfall(interest_first.clone()), Ok(()));
+            assert_eq!(interest_first, loan.get_payment_waterfall());
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.set_payment_waterfall(DEFAULT_PAYMENT_WATERFALL.to_vec()),
+                Err(LoanError::MissingRole(ADMIN))
+            );
+        }
+
+        #[ink::test]
+        fn queries_do_not_trap_on_missing_loans() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            assert_eq!(QUERY_VERSION, loan.query_version());
+            assert!(loan.try_get_loan_info(1).is_none());
+            assert!(!loan.loan_exists(1));
+            assert!(loan.loans_of_borrower(accounts.bob, 0, 10).is_empty());
+            mint_collateral(&mut loan, accounts.bob, 0);
+            mint_collateral(&mut loan, accounts.bob, 1);
+            for item_id in 0..2 {
+                pay_with_call!(
+                    loan.create_loan(
+                        accounts.alice,
+                        accounts.bob,
+                        0,
+                        item_id,
+                        2000,
+                        1000,
+                        terms(0)
+                    ),
+                    1000
+                )
+                .unwrap();
+            }
+            assert!(loan.loan_exists(1));
+            assert_eq!(Some(1), loan.try_get_loan_info(2).map(|info| info.item_id));
+            assert_eq!(vec![1, 2], loan.loans_of_borrower(accounts.bob, 0, 10));
+            assert_eq!(vec![2], loan.loans_of_borrower(accounts.bob, 1, 10));
+            assert_eq!(vec![1], loan.loans_of_borrower(accounts.bob, 0, 1));
+            assert!(loan.loans_of_borrower(accounts.charlie, 0, 10).is_empty());
+        }
+
+        /// Checks that the aggregates of every asset equal the sum over its stored loans
+        fn assert_stats_match_loans(loan: &LoanContract) {
+            let mut expected: Vec<(LoanAsset, PortfolioStats)> = Vec::new();
+            let mut native_committed = 0;
+            for loan_id in 1..=loan.last_loan_id.get_or_default() {
+                if let Some(loan_info) = loan.loan_info.get(loan_id) {
+                    let position = match expected
+                        .iter()
+                        .position(|(asset, _)| *asset == loan_info.asset)
+                    {
+                        Some(position) => position,
+                        None => {
+                            let stats = loan.portfolio_stats(loan_info.asset);
+                            expected.push((
+                                loan_info.asset,
+                                PortfolioStats {
+                                    total_interest: stats.total_interest,
+                                    total_repaid: stats.total_repaid,
+                                    ..Default::default()
+                                },
+                            ));
+                            expected.len() - 1
+                        }
+                    };
+                    expected[position].1.add_loan(&loan_info).unwrap();
+                    if loan_info.asset == LoanAsset::Native {
+                        native_committed += loan_info.available_amount;
+                    }
+                }
+            }
+            for (asset, stats) in expected {
+                assert_eq!(stats, loan.portfolio_stats(asset));
+            }
+            assert_eq!(native_committed, loan.native_committed.get_or_default());
+        }
+
+        #[ink::test]
+        fn portfolio_stats_follow_the_loans() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            assert_eq!(
+                PortfolioStats::default(),
+                loan.portfolio_stats(LoanAsset::Native)
+            );
+            set_balance(accounts.bob, 10_000);
+            for item_id in 0..2 {
+                mint_collateral(&mut loan, accounts.bob, item_id);
+                pay_with_call!(
+                    loan.create_loan(
+                        accounts.alice,
+                        accounts.bob,
+                        0,
+                        item_id,
+                        2000,
+                        1000,
+                        terms(1000)
+                    ),
+                    1000
+                )
+                .unwrap();
+            }
+            assert_eq!(
+                2000,
+                loan.portfolio_stats(LoanAsset::Native).total_committed
+            );
+            assert_eq!(
+                2,
+                loan.portfolio_stats(LoanAsset::Native)
+                    .loans_in(LoanStatus::Pending)
+            );
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 400), Ok(()));
+            assert_stats_match_loans(&loan);
+            set_timestamp(MILLISECONDS_PER_YEAR);
+            set_sender(accounts.alice);
+            assert_eq!(loan.charge_apy(1), Ok(()));
+            assert_eq!(loan.update_loan_status(1, LoanStatus::Delinquent), Ok(()));
+            let stats = loan.portfolio_stats(LoanAsset::Native);
+            assert_eq!(1600, stats.total_committed);
+            assert_eq!(40, stats.total_interest);
+            assert_eq!(440, stats.total_outstanding);
+            assert_eq!(1, stats.loans_in(LoanStatus::Pending));
+            assert_eq!(0, stats.loans_in(LoanStatus::Active));
+            assert_eq!(1, stats.loans_in(LoanStatus::Delinquent));
+            assert_stats_match_loans(&loan);
+        }
+
+        fn loan_ids(summaries: Vec<LoanSummary>) -> Vec<Id> {
+            summaries.iter().map(|summary| summary.loan_id).collect()
+        }
+
+        #[ink::test]
+        fn loans_are_indexed_by_borrower_lender_and_collection() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            assert_eq!(loan.register_collection(1, nft_contract()), Ok(()));
+            let loans = [
+                (accounts.alice, accounts.bob, 0),
+                (accounts.charlie, accounts.bob, 1),
+                (accounts.alice, accounts.django, 0),
+                (accounts.alice, accounts.bob, 1),
+            ];
+            for (item_id, (lender, borrower, collection_id)) in loans.into_iter().enumerate() {
+                mint_collateral(&mut loan, borrower, item_id as u32);
+                assert_eq!(
+                    loan.update_valuation(collection_id, item_id as u32, 2000),
+                    Ok(())
+                );
+                pay_with_call!(
+                    loan.create_loan(
+                        lender,
+                        borrower,
+                        collection_id,
+                        item_id as u32,
+                        2000,
+                        1000,
+                        terms(0)
+                    ),
+                    1000
+                )
+                .unwrap();
+            }
+            assert_eq!(
+                vec![1, 2, 4],
+                loan_ids(loan.loans_by_borrower(accounts.bob, 0, 10))
+            );
+            assert_eq!(
+                vec![3],
+                loan_ids(loan.loans_by_borrower(accounts.django, 0, 10))
+            );
+            assert_eq!(
+                vec![1, 3, 4],
+                loan_ids(loan.loans_by_lender(accounts.alice, 0, 10))
+            );
+            assert_eq!(
+                vec![2],
+                loan_ids(loan.loans_by_lender(accounts.charlie, 0, 10))
+            );
+            assert_eq!(vec![1, 3], loan_ids(loan.loans_by_collection(0, 0, 10)));
+            assert_eq!(vec![2, 4], loan_ids(loan.loans_by_collection(1, 0, 10)));
+            assert!(loan.loans_by_collection(2, 0, 10).is_empty());
+            let summary = &loan.loans_by_borrower(accounts.django, 0, 1)[0];
+            assert_eq!(loan.get_loan_info(3).summary(3), *summary);
+            assert_eq!(accounts.alice, summary.lender);
+            assert_eq!(2, summary.item_id);
+            assert_eq!(1000, summary.available_amount);
+            assert_eq!(LoanStatus::Pending, summary.status);
+        }
+
+        #[ink::test]
+        fn loan_index_queries_are_paginated() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            for item_id in 0..5 {
+                mint_collateral(&mut loan, accounts.bob, item_id);
+                pay_with_call!(
+                    loan.create_loan(
+                        accounts.alice,
+                        accounts.bob,
+                        0,
+                        item_id,
+                        2000,
+                        1000,
+                        terms(0)
+                    ),
+                    1000
+                )
+                .unwrap();
+            }
+            assert_eq!(
+                vec![1, 2],
+                loan_ids(loan.loans_by_borrower(accounts.bob, 0, 2))
+            );
+            assert_eq!(
+                vec![3, 4],
+                loan_ids(loan.loans_by_lender(accounts.alice, 2, 2))
+            );
+            assert_eq!(vec![5], loan_ids(loan.loans_by_collection(0, 4, 2)));
+            assert!(loan.loans_by_borrower(accounts.bob, 5, 2).is_empty());
+            assert!(loan
+                .loans_by_borrower(accounts.bob, u32::MAX, u32::MAX)
+                .is_empty());
+            assert!(loan.loans_by_borrower(accounts.bob, 0, 0).is_empty());
+            // The last loan of the entry takes the position of a removed loan
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, LOAN_ORIGINATOR, Some(accounts.charlie)),
+                Ok(())
+            );
+            assert_eq!(loan.transfer_loan(2, accounts.charlie), Ok(()));
+            assert_eq!(
+                vec![1, 5, 3, 4],
+                loan_ids(loan.loans_by_lender(accounts.alice, 0, 10))
+            );
+            assert_eq!(
+                vec![2],
+                loan_ids(loan.loans_by_lender(accounts.charlie, 0, 10))
+            );
+            assert_eq!(loan.transfer_loan(4, accounts.charlie), Ok(()));
+            assert_eq!(
+                vec![1, 5, 3],
+                loan_ids(loan.loans_by_lender(accounts.alice, 0, 10))
+            );
+        }
+
+        #[ink::test]
+        fn transfer_loan_moves_loan_to_new_lender() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            assert_eq!(
+                loan.transfer_loan(1, accounts.charlie),
+                Err(LoanError::NewLenderNotOriginator)
+            );
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, LOAN_ORIGINATOR, Some(accounts.charlie)),
+                Ok(())
+            );
+            assert_eq!(loan.transfer_loan(1, accounts.charlie), Ok(()));
+            match last_event() {
+                Event::LoanTransferred(event) => {
+                    assert_eq!(1, event.loan_id);
+                    assert_eq!(accounts.alice, event.from);
+                    assert_eq!(accounts.charlie, event.to);
+                }
+                _ => panic!("expected LoanTransferred event"),
+            }
+            assert_eq!(accounts.charlie, loan.get_loan_info(1).lender);
+            assert!(loan.loans_by_lender(accounts.alice, 0, 10).is_empty());
+            assert_eq!(
+                vec![1],
+                loan_ids(loan.loans_by_lender(accounts.charlie, 0, 10))
+            );
+            assert_eq!(
+                vec![1],
+                loan_ids(loan.loans_by_borrower(accounts.bob, 0, 10))
+            );
+            set_sender(accounts.charlie);
+            assert_eq!(loan.update_loan(1, 0), Ok(()));
+            set_sender(accounts.alice);
+            assert_eq!(loan.update_loan(1, 0), Err(LoanError::NoPermission));
+        }
+
+        #[ink::test]
+        fn transfer_loan_fails_if_someone_but_the_lender_calls() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            assert_eq!(
+                loan.transfer_loan(1, accounts.charlie),
+                Err(LoanError::NonExistingLoanId)
+            );
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            assert_eq!(
+                loan.transfer_loan(1, accounts.alice),
+                Err(LoanError::SameLender)
+            );
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.transfer_loan(1, accounts.bob),
+                Err(LoanError::NoPermission)
+            );
+            assert_eq!(
+                vec![1],
+                loan_ids(loan.loans_by_lender(accounts.alice, 0, 10))
+            );
+        }
+
+        #[ink::test]
+        fn delete_loan_fails_non_existing_loanid() {
+            let mut loan = create_contract();
+            assert_eq!(loan.delete_loan(1), Err(LoanError::NonExistingLoanId));
+        }
+
+        #[ink::test]
+        fn update_loan_fails_non_existing_loanid() {
+            let mut loan = create_contract();
+            assert_eq!(
+                pay_with_call!(loan.update_loan(1, 100), 100),
+                Err(LoanError::NonExistingLoanId)
+            );
+        }
+
+        #[ink::test]
+        fn charge_apy_fails_non_existing_loanid() {
+            let mut loan = create_contract();
+            assert_eq!(loan.charge_apy(1), Err(LoanError::NonExistingLoanId));
+        }
+
+        #[ink::test]
+        fn repay_fails_non_existing_loanid() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            set_sender(accounts.bob);
+            assert_eq!(
+                pay_with_call!(loan.repay(1, 100), 100),
+                Err(LoanError::NonExistingLoanId)
+            );
+        }
+
+        #[ink::test]
+        fn liquidate_fails_non_existing_loanid() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, LIQUIDATOR, Some(accounts.alice)),
+                Ok(())
+            );
+            assert_eq!(loan.liquidate(1), Err(LoanError::NonExistingLoanId));
+            assert_eq!(
+                pay_with_call!(loan.distribute_recovery(1, 100), 100),
+                Err(LoanError::NonExistingLoanId)
+            );
+        }
+
+        #[ink::test]
+        fn update_loan_status_fails_non_existing_loanid() {
+            let mut loan = create_contract();
+            assert_eq!(
+                loan.update_loan_status(1, LoanStatus::Defaulted),
+                Err(LoanError::NonExistingLoanId)
+            );
+        }
+
+        #[ink::test]
+        fn repay_fails_if_amount_exceeds_debt() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            set_sender(accounts.bob);
+            assert_eq!(Ok(()), loan.withdraw_funds(1, 300));
+            assert_eq!(
+                pay_with_call!(loan.repay(1, 301), 301),
+                Err(LoanError::RepayExceedsDebt)
+            );
+            assert_eq!(300, loan.get_loan_info(1).borrowed_amount);
+        }
+
+        #[ink::test]
+        fn amounts_beyond_the_balance_range_overflow() {
+            let mut loan = create_contract();
+            loan.decimals.set(&MAX_DECIMALS);
+            assert_eq!(Ok(3 * 10u128.pow(38)), loan._to_planck(3));
+            assert_eq!(Err(LoanError::Overflow), loan._to_planck(4));
+            assert_eq!(
+                Err(LoanError::Overflow),
+                LoanContract::_calculate_interest(
+                    Balance::MAX,
+                    2 * BASIS_POINTS,
+                    MILLISECONDS_PER_YEAR,
+                    0
+                )
+            );
+            loan.last_loan_id.set(&Id::MAX);
+            assert_eq!(
+                Err(LoanError::Overflow),
+                loan._get_next_loan_id_and_increase()
+            );
+        }
+
+        #[ink::test]
+        fn full_histories_and_indexes_overflow() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            loan.valuation_count.insert((0, 0), &u32::MAX);
+            assert_eq!(loan.update_valuation(0, 0, 2000), Err(LoanError::Overflow));
+            loan.valuation_count.insert((0, 0), &1);
+            loan.borrower_loan_count.insert(accounts.bob, &u32::MAX);
+            assert_eq!(
+                pay_with_call!(
+                    loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                    1000
+                ),
+                Err(LoanError::Overflow)
+            );
+        }
+
+        #[derive(Debug, Clone)]
+        enum Operation {
+            Withdraw(Balance),
+            TopUp(Balance),
+            ChargeInterest(Timestamp),
+            RepayAboveDebt(Balance),
+        }
+
+        fn operation() -> impl Strategy<Value = Operation> {
+            prop_oneof![
+                (0..1500u128).prop_map(Operation::Withdraw),
+                (0..500u128).prop_map(Operation::TopUp),
+                (0..MILLISECONDS_PER_YEAR).prop_map(Operation::ChargeInterest),
+                (1..1000u128).prop_map(Operation::RepayAboveDebt),
+            ]
+        }
+
+        proptest! {
+            #[test]
+            fn operation_sequences_keep_balances_consistent(
+                interest_rate in 0..5000u32,
+                operations in proptest::collection::vec(operation(), 1..30),
+            ) {
+                run_test::<ink::env::DefaultEnvironment, _>(|accounts| {
+                    let mut loan = create_contract();
+                    assert_eq!(loan.set_max_valuation_age(Timestamp::MAX), Ok(()));
+                    set_balance(accounts.bob, 1_000_000);
+                    mint_collateral(&mut loan, accounts.bob, 0);
+                    assert_eq!(loan.update_valuation(0, 0, 10_000), Ok(()));
+                    pay_with_call!(
+                        loan.create_loan(
+                            accounts.alice,
+                            accounts.bob,
+                            0,
+                            0,
+                            10_000,
+                            1000,
+                            terms(interest_rate)
+                        ),
+                        1000
+                    )
+                    .unwrap();
+                    let mut now = 0;
+                    let mut withdrawn = 0;
+                    for operation in operations {
+                        if let Operation::ChargeInterest(elapsed) = operation {
+                            now += elapsed;
+                            set_timestamp(now);
+                        }
+                        let before = loan.get_loan_info(1);
+                        let result = match operation {
+                            Operation::Withdraw(amount) => {
+                                set_sender(accounts.bob);
+                                loan.withdraw_funds(1, amount)
+                            }
+                            Operation::TopUp(amount) => {
+                                set_sender(accounts.alice);
+                                pay_with_call!(loan.update_loan(1, amount), amount)
+                            }
+                            Operation::ChargeInterest(_) => {
+                                set_sender(accounts.alice);
+                                loan.charge_apy(1)
+                            }
+                            Operation::RepayAboveDebt(excess) => {
+                                set_sender(accounts.bob);
+                                let amount = before.borrowed_amount + excess;
+                                pay_with_call!(loan.repay(1, amount), amount)
+                            }
+                        };
+                        let after = loan.get_loan_info(1);
+                        let expected = match (&operation, &result) {
+                            (Operation::Withdraw(amount), Ok(())) => (
+                                before.borrowed_amount + amount,
+                                before.available_amount - amount,
+                            ),
+                            (Operation::TopUp(amount), Ok(())) => (
+                                before.borrowed_amount,
+                                before.available_amount + amount,
+                            ),
+                            (Operation::RepayAboveDebt(_), Ok(())) => {
+                                panic!("repaid more than the debt")
+                            }
+                            _ => (before.borrowed_amount, before.available_amount),
+                        };
+                        assert_eq!(expected, (after.borrowed_amount, after.available_amount));
+                        if let (Operation::Withdraw(amount), Ok(())) = (&operation, &result) {
+                            withdrawn += amount;
+                        }
+                        assert_stats_match_loans(&loan);
+                        let debt = &after.debt;
+                        assert_eq!(
+                            debt.principal + debt.interest + debt.fees + debt.penalties,
+                            after.borrowed_amount
+                        );
+                        let stats = loan.portfolio_stats(LoanAsset::Native);
+                        assert_eq!(
+                            withdrawn + stats.total_interest - stats.total_repaid,
+                            stats.total_outstanding
+                        );
+                    }
+                    Ok(())
+                })
+                .unwrap();
+            }
+
+            #[test]
+            fn interest_calculation_overflows_instead_of_wrapping(
+                amount in any::<Balance>(),
+                interest_rate in any::<u32>(),
+                elapsed in any::<Timestamp>(),
+                carried in 0..INTEREST_PRECISION,
+            ) {
+                match LoanContract::_calculate_interest(amount, interest_rate, elapsed, carried) {
+                    Ok((interest, remainder)) => {
+                        prop_assert!(remainder < INTEREST_PRECISION);
+                        // The interest is at least the interest on the whole multiples
+                        let rate_time = interest_rate as Balance * elapsed as Balance;
+                        prop_assert!(interest >= (amount / INTEREST_PRECISION) * rate_time);
+                    }
+                    Err(error) => {
+                        prop_assert_eq!(LoanError::Overflow, error);
+                        prop_assert!(amount > 0 && interest_rate > 0 && elapsed > 0);
+                    }
+                }
+            }
+
+            #[test]
+            fn recovery_split_adds_up_to_the_proceeds(
+                outstanding in any::<u64>(),
+                amount in any::<u64>(),
+                lender_share in 0..=BASIS_POINTS,
+            ) {
+                let (lender_amount, pool_amount, borrower_amount) =
+                    LoanContract::_split_recovery(outstanding as Balance, amount as Balance, lender_share)
+                        .unwrap();
+                prop_assert_eq!(amount as Balance, lender_amount + pool_amount + borrower_amount);
+                prop_assert!(lender_amount + pool_amount <= outstanding as Balance);
+            }
+        }
+
+        #[ink::test]
+        fn excess_value_is_credited_to_the_caller() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            set_balance(accounts.alice, 10_000);
+            mint_collateral(&mut loan, accounts.bob, 0);
+            assert_eq!(
+                pay_with_call!(
+                    loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                    1200
+                ),
+                Ok(())
+            );
+            match last_events(2).remove(0) {
+                Event::CreditAdded(event) => {
+                    assert_eq!(accounts.alice, event.account);
+                    assert_eq!((200, 200), (event.amount, event.credit));
+                }
+                _ => panic!("expected CreditAdded event"),
+            }
+            assert_eq!(pay_with_call!(loan.update_loan(1, 100), 150), Ok(()));
+            assert_eq!(250, loan.get_credit(accounts.alice));
+            assert_eq!(0, loan.get_credit(accounts.bob));
+            assert_eq!(1100, loan.get_loan_info(1).available_amount);
+        }
+
+        #[ink::test]
+        fn credit_is_not_lent_out() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            // The balance of the contract only covers the loan and the credit
+            set_balance(contract_id(), 1200);
+            loan.credits.insert(accounts.alice, &300);
+            loan.total_credit.set(&300);
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.withdraw_funds(1, 1000),
+                Err(LoanError::InsufficientLoanBalance)
+            );
+            assert_eq!(loan.withdraw_funds(1, 900), Ok(()));
+        }
+
+        #[ink::test]
+        fn loans_only_draw_their_own_funding() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            for item_id in 0..2 {
+                mint_collateral(&mut loan, accounts.bob, item_id);
+                pay_with_call!(
+                    loan.create_loan(
+                        accounts.alice,
+                        accounts.bob,
+                        0,
+                        item_id,
+                        2000,
+                        1000,
+                        terms(0)
+                    ),
+                    1000
+                )
+                .unwrap();
+            }
+            // The balance of the contract falls short of the funds of both loans
+            set_balance(contract_id(), 1500);
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.withdraw_funds(1, 501),
+                Err(LoanError::InsufficientLoanBalance)
+            );
+            assert_eq!(loan.withdraw_funds(1, 500), Ok(()));
+            assert_eq!(
+                loan.withdraw_funds(2, 501),
+                Err(LoanError::InsufficientLoanBalance)
+            );
+            assert_eq!(loan.withdraw_funds(2, 500), Ok(()));
+        }
+
+        #[ink::test]
+        fn check_invariants_reports_deficit() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1000
+            )
+            .unwrap();
+            set_balance(contract_id(), 1200);
+            let report = loan.check_invariants().unwrap();
+            assert!(report.is_solvent());
+            assert_eq!(1200, report.balance);
+            assert_eq!(1000, report.committed);
+            loan.credits.insert(accounts.alice, &300);
+            loan.total_credit.set(&300);
+            let report = loan.check_invariants().unwrap();
+            assert!(!report.is_solvent());
+            assert_eq!(300, report.credit);
+            assert_eq!(100, report.deficit);
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.check_invariants(),
+                Err(LoanError::MissingRole(AUDITOR))
+            );
+            set_sender(accounts.alice);
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, AUDITOR, Some(accounts.bob)),
+                Ok(())
+            );
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 400), Ok(()));
+            let report = loan.check_invariants().unwrap();
+            assert_eq!(600, report.committed);
+            assert_eq!(100, report.deficit);
+        }
+
+        #[ink::test]
+        fn withdraw_credit_works() {
+            let accounts = default_accounts();
+            let mut loan = create_contract();
+            set_balance(accounts.alice, 10_000);
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1200
+            )
+            .unwrap();
+            let alice_balance_before =
+                get_account_balance::<ink::env::DefaultEnvironment>(accounts.alice).unwrap();
+            assert_eq!(loan.withdraw_credit(), Ok(()));
+            assert_eq!(
+                Ok(alice_balance_before + 200),
+                get_account_balance::<ink::env::DefaultEnvironment>(accounts.alice)
+            );
+            match last_event() {
+                Event::CreditWithdrawn(event) => {
+                    assert_eq!(accounts.alice, event.account);
+                    assert_eq!(200, event.amount);
+                }
+                _ => panic!("expected CreditWithdrawn event"),
+            }
+            assert_eq!(0, loan.get_credit(accounts.alice));
+            assert_eq!(loan.withdraw_credit(), Err(LoanError::NoCredit));
+        }
+
+        #[ink::test]
+        fn env_errors_are_mapped_to_loan_errors() {
+            use ink::env::Error as EnvError;
+
+            let cases = [
+                (EnvError::CallRuntimeFailed, LoanError::CallRuntimeFailed),
+                (
+                    EnvError::Decode(scale::Error::from("invalid")),
+                    LoanError::DecodeFailed,
+                ),
+                (EnvError::CalleeTrapped, LoanError::CalleeTrapped),
+                (EnvError::CalleeReverted, LoanError::CalleeReverted),
+                (EnvError::KeyNotFound, LoanError::KeyNotFound),
+                (
+                    EnvError::_BelowSubsistenceThreshold,
+                    LoanError::BelowSubsistenceThreshold,
+                ),
+                (EnvError::TransferFailed, LoanError::TransferFailed),
+                (EnvError::_EndowmentTooLow, LoanError::EndowmentTooLow),
+                (EnvError::CodeNotFound, LoanError::CodeNotFound),
+                (EnvError::NotCallable, LoanError::NotCallable),
+                (EnvError::LoggingDisabled, LoanError::LoggingDisabled),
+                (
+                    EnvError::EcdsaRecoveryFailed,
+                    LoanError::EcdsaRecoveryFailed,
+                ),
+                (EnvError::Unknown, LoanError::UnknownEnvError),
+            ];
+            for (env_error, loan_error) in cases {
+                assert_eq!(loan_error, LoanError::from(env_error));
+            }
+        }
+
+        fn encoded_call<Call>(indices: RuntimeCallIndices, call: Call) -> Vec<u8>
+        where
+            RuntimeCall<Call>: scale::Encode,
+        {
+            scale::Encode::encode(&RuntimeCall { indices, call })
+        }
+
+        #[ink::test]
+        fn asset_calls_encode_compact_parameters() {
+            let accounts = default_accounts();
+            let indices = RuntimeCallIndices::default();
+            let mut transfer = vec![8, 8, 0x04, 0];
+            transfer.extend_from_slice(accounts.bob.as_ref());
+            transfer.extend([0xa1, 0x0f]);
+            assert_eq!(
+                transfer,
+                encoded_call(
+                    indices,
+                    AssetsCall::Transfer {
+                        id: 1,
+                        target: accounts.bob,
+                        amount: 1000
+                    }
+                )
+            );
+            let mut transfer_approved = vec![8, 25, 0x04, 0];
+            transfer_approved.extend_from_slice(accounts.alice.as_ref());
+            transfer_approved.push(0);
+            transfer_approved.extend_from_slice(accounts.bob.as_ref());
+            transfer_approved.push(0x0c);
+            assert_eq!(
+                transfer_approved,
+                encoded_call(
+                    indices,
+                    AssetsCall::TransferApproved {
+                        id: 1,
+                        owner: accounts.alice,
+                        destination: accounts.bob,
+                        amount: 3
+                    }
+                )
+            );
+        }
+
+        #[ink::test]
+        fn runtime_calls_encode_with_default_indices() {
+            let indices = RuntimeCallIndices::default();
+            assert_eq!(
+                vec![7, 3, 1, 0, 0, 0],
+                encoded_call(indices, CommunityLoanPoolCall::DeleteLoan { loan_id: 1 })
+            );
+            let mut update_loan = vec![7, 4, 1, 0, 0, 0, 0xf4, 0x01];
+            update_loan.extend([0; 14]);
+            assert_eq!(
+                update_loan,
+                encoded_call(
+                    indices,
+                    CommunityLoanPoolCall::UpdateLoan {
+                        loan_id: 1,
+                        amount: 500
+                    }
+                )
+            );
+            let mut liquidate_loan = vec![7, 5, 2, 0, 0, 0, 0x2c, 0x01];
+            liquidate_loan.extend([0; 14]);
+            assert_eq!(
+                liquidate_loan,
+                encoded_call(
+                    indices,
+                    CommunityLoanPoolCall::LiquidateLoan {
+                        loan_id: 2,
+                        shortfall: 300
+                    }
+                )
+            );
+        }
+
+        #[ink::test]
+        fn runtime_calls_encode_with_configured_indices() {
+            let mut loan = create_contract();
+            let indices = RuntimeCallIndices {
+                community_loan_pool: 42,
+                delete_loan: 0,
+                update_loan: 1,
+                liquidate_loan: 9,
+                ..Default::default()
+            };
+            assert_eq!(loan.set_call_indices(indices), Ok(()));
+            assert_eq!(indices, loan.get_call_indices());
+            assert_eq!(
+                vec![42, 0, 0xff, 0xff, 0xff, 0xff],
+                encoded_call(
+                    loan.get_call_indices(),
+                    CommunityLoanPoolCall::DeleteLoan { loan_id: u32::MAX }
+                )
+            );
+            let mut update_loan = vec![42, 1, 1, 0, 0, 0];
+            update_loan.extend([0xff; 16]);
+            assert_eq!(
+                update_loan,
+                encoded_call(
+                    loan.get_call_indices(),
+                    CommunityLoanPoolCall::UpdateLoan {
+                        loan_id: 1,
+                        amount: u128::MAX
+                    }
+                )
+            );
+            assert_eq!(
+                vec![42, 9, 3, 0, 0, 0, 1],
+                encoded_call(
+                    loan.get_call_indices(),
+                    CommunityLoanPoolCall::LiquidateLoan {
+                        loan_id: 3,
+                        shortfall: 1
+                    }
+                )[..7]
+                    .to_vec()
             );
-            assert_eq!(result, Err(LoanError::NotEnoughFundsProvided));
         }
 
         #[ink::test]
-        fn increase_loan_id_works() {
+        fn set_call_indices_validates_indices() {
             let accounts = default_accounts();
             let mut loan = create_contract();
-            let result = pay_with_call!(
-                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000),
-                1000
+            let indices = RuntimeCallIndices {
+                update_loan: 3,
+                ..Default::default()
+            };
+            assert_eq!(
+                loan.set_call_indices(indices),
+                Err(LoanError::InvalidCallIndices)
             );
-            assert_eq!(result, Ok(()));
-            loan.get_loan_info(1);
-            let result = pay_with_call!(
-                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000),
-                1000
+            let indices = RuntimeCallIndices {
+                assets: 7,
+                ..Default::default()
+            };
+            assert_eq!(
+                loan.set_call_indices(indices),
+                Err(LoanError::InvalidCallIndices)
+            );
+            assert_eq!(RuntimeCallIndices::default(), loan.get_call_indices());
+            set_sender(accounts.bob);
+            assert_eq!(
+                loan.set_call_indices(RuntimeCallIndices::default()),
+                Err(LoanError::MissingRole(ADMIN))
             );
-            assert_eq!(result, Ok(()));
-            loan.get_loan_info(2);
         }
 
         #[ink::test]
-        fn withdraw_works() {
+        fn pause_freezes_value_moving_messages() {
             let accounts = default_accounts();
             let mut loan = create_contract();
-            let result = pay_with_call!(
-                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000),
-                1000
+            set_balance(accounts.alice, 10_000);
+            mint_collateral(&mut loan, accounts.bob, 0);
+            pay_with_call!(
+                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                1200
+            )
+            .unwrap();
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, PAUSER, Some(accounts.alice)),
+                Ok(())
             );
-            assert_eq!(result, Ok(()));
-            let contract_balance_before = ink::env::balance::<ink::env::DefaultEnvironment>();
-            let bob_balance_before =
-                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob);
-            assert_eq!(Ok(1000), bob_balance_before);
-            assert_eq!(2000, contract_balance_before);
+            assert_eq!(loan.pause(PauseScope::All), Ok(()));
+            match last_event() {
+                Event::Paused(event) => {
+                    assert_eq!(PauseScope::All, event.scope);
+                    assert_eq!(accounts.alice, event.account);
+                }
+                _ => panic!("expected Paused event"),
+            }
+            let paused = Err(LoanError::Paused(PauseScope::All));
+            mint_collateral(&mut loan, accounts.bob, 1);
+            assert_eq!(
+                pay_with_call!(
+                    loan.create_loan(accounts.alice, accounts.bob, 0, 1, 2000, 1000, terms(0)),
+                    1000
+                ),
+                paused
+            );
+            assert_eq!(pay_with_call!(loan.update_loan(1, 100), 100), paused);
+            assert_eq!(loan.delete_loan(1), paused);
+            assert_eq!(loan.withdraw_credit(), paused);
+            assert_eq!(loan.charge_apy(1), Ok(()));
             set_sender(accounts.bob);
-            let result = loan.withdraw_funds(1, 500);
-            assert_eq!(Ok(()), result);
-            let contract_balance_after = ink::env::balance::<ink::env::DefaultEnvironment>();
-            let bob_balance_after =
-                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob);
-            assert_eq!(Ok(1500), bob_balance_after);
-            assert_eq!(1500, contract_balance_after);
-            let loan_info = loan.get_loan_info(1);
-            assert_eq!(500, loan_info.borrowed_amount);
-            assert_eq!(500, loan_info.available_amount);
-            let result = loan.withdraw_funds(1, 500);
-            let loan_info = loan.get_loan_info(1);
-            assert_eq!(1000, loan_info.borrowed_amount);
-            assert_eq!(0, loan_info.available_amount);
+            assert_eq!(loan.withdraw_funds(1, 500), paused);
+            set_sender(accounts.alice);
+            assert_eq!(loan.unpause(PauseScope::All), Ok(()));
+            match last_event() {
+                Event::Unpaused(event) => assert_eq!(PauseScope::All, event.scope),
+                _ => panic!("expected Unpaused event"),
+            }
+            assert_eq!(loan.withdraw_credit(), Ok(()));
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 500), Ok(()));
         }
 
         #[ink::test]
-        fn withdraw_fails_non_existing_loanid() {
+        fn pause_scopes_freeze_only_their_loans() {
             let accounts = default_accounts();
             let mut loan = create_contract();
-            let result = pay_with_call!(
-                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000),
-                1000
+            set_balance(accounts.alice, 10_000);
+            for item_id in 0..2 {
+                mint_collateral(&mut loan, accounts.bob, item_id);
+                pay_with_call!(
+                    loan.create_loan(
+                        accounts.alice,
+                        accounts.bob,
+                        0,
+                        item_id,
+                        2000,
+                        1000,
+                        terms(0)
+                    ),
+                    1000
+                )
+                .unwrap();
+            }
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, PAUSER, Some(accounts.alice)),
+                Ok(())
             );
-            assert_eq!(result, Ok(()));
-            let contract_balance_before = ink::env::balance::<ink::env::DefaultEnvironment>();
-            let bob_balance_before =
-                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob);
-            assert_eq!(Ok(1000), bob_balance_before);
-            assert_eq!(2000, contract_balance_before);
+            assert_eq!(loan.pause(PauseScope::Loan(1)), Ok(()));
             set_sender(accounts.bob);
-            let result = loan.withdraw_funds(3, 500);
-            assert_eq!(Err(LoanError::NonExistingLoanId), result);
+            assert_eq!(
+                loan.withdraw_funds(1, 100),
+                Err(LoanError::Paused(PauseScope::Loan(1)))
+            );
+            assert_eq!(loan.withdraw_funds(2, 100), Ok(()));
+            set_sender(accounts.alice);
+            assert_eq!(loan.unpause(PauseScope::Loan(1)), Ok(()));
+            assert_eq!(loan.pause(PauseScope::Collection(0)), Ok(()));
+            mint_collateral(&mut loan, accounts.bob, 2);
+            assert_eq!(
+                pay_with_call!(
+                    loan.create_loan(accounts.alice, accounts.bob, 0, 2, 2000, 1000, terms(0)),
+                    1000
+                ),
+                Err(LoanError::Paused(PauseScope::Collection(0)))
+            );
+            set_sender(accounts.bob);
+            for loan_id in 1..=2 {
+                assert_eq!(
+                    loan.withdraw_funds(loan_id, 100),
+                    Err(LoanError::Paused(PauseScope::Collection(0)))
+                );
+            }
         }
 
         #[ink::test]
-        fn withdraw_fails_if_someone_but_the_borrower_calls() {
+        fn pause_requires_pauser_role() {
             let accounts = default_accounts();
             let mut loan = create_contract();
-            pay_with_call!(
-                loan.create_loan(accounts.bob, accounts.alice, 0, 0, 2000, 1000),
-                1000
+            assert_eq!(
+                loan.pause(PauseScope::All),
+                Err(LoanError::MissingRole(PAUSER))
+            );
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, PAUSER, Some(accounts.alice)),
+                Ok(())
+            );
+            assert!(!loan.is_paused(PauseScope::Loan(1)));
+            assert_eq!(loan.pause(PauseScope::Loan(1)), Ok(()));
+            assert!(loan.is_paused(PauseScope::Loan(1)));
+            assert_eq!(
+                loan.pause(PauseScope::Loan(1)),
+                Err(LoanError::Paused(PauseScope::Loan(1)))
             );
+            assert_eq!(loan.unpause(PauseScope::All), Err(LoanError::NotPaused));
             set_sender(accounts.bob);
-            let result = loan.withdraw_funds(1, 500);
-            assert_eq!(Err(LoanError::NotTheBorrower), result);
-            set_sender(accounts.charlie);
-            let result = loan.withdraw_funds(1, 500);
-            assert_eq!(Err(LoanError::NotTheBorrower), result);
-            let contract_balance_after = ink::env::balance::<ink::env::DefaultEnvironment>();
-            assert_eq!(2000, contract_balance_after);
+            assert_eq!(
+                loan.unpause(PauseScope::Loan(1)),
+                Err(LoanError::MissingRole(PAUSER))
+            );
+            assert!(loan.is_paused(PauseScope::Loan(1)));
+        }
+
+        /// Returns the contract as it is decoded after an upgrade from a version whose root is
+        /// written to the raw storage
+        fn upgraded_contract(root: &impl Storable) -> LoanContract {
+            let key = <LoanContract as StorageKey>::KEY;
+            ink::env::set_contract_storage(&key, root);
+            ink::env::get_contract_storage::<_, LoanContract>(&key)
+                .unwrap()
+                .unwrap()
+        }
+
+        /// Returns the contract after an upgrade from version 0 with loan 1 pending, loan 2
+        /// deleted and loan 3 active, stored as the first release encoded its root and loans
+        fn upgraded_from_version_0() -> LoanContract {
+            let accounts = default_accounts();
+            // The root held the last loan id and the community-loan-pool account
+            let loan = upgraded_contract(&(3 as Id, accounts.frank));
+            for (loan_id, borrowed_amount) in [(1, 0), (3, 400)] {
+                // The lender, borrower, collection id, item id, collateral price,
+                // available amount, borrowed amount and timestamp of the loan
+                let loan_v0 = (
+                    accounts.alice,
+                    accounts.bob,
+                    0u32,
+                    0u32,
+                    2000 as Balance,
+                    (1000 - borrowed_amount) as Balance,
+                    borrowed_amount as Balance,
+                    0 as Timestamp,
+                );
+                ink::env::set_contract_storage(
+                    &(StorageKey::key(&loan.loan_info), loan_id as Id),
+                    &loan_v0,
+                );
+            }
+            loan
         }
 
         #[ink::test]
-        fn withdraw_fails_insufficient_funds() {
+        fn migrate_converts_loans_from_version_0() {
+            let accounts = default_accounts();
+            let mut loan = upgraded_from_version_0();
+            assert_eq!(loan.set_max_ltv(5000), Err(LoanError::MigrationPending));
+            set_timestamp(1000);
+            let terms = LoanTerms {
+                maturity: 1000 + 10 * MILLISECONDS_PER_YEAR,
+                ..terms(500)
+            };
+            set_sender(accounts.frank);
+            assert_eq!(loan.migrate(2, terms.clone()), Ok(false));
+            assert_eq!(0, loan.get_storage_version());
+            assert_eq!(Some(accounts.frank), Ownable::owner(&loan));
+            assert_eq!(12, loan.decimals());
+            assert_eq!(DEFAULT_MAX_LTV, loan.get_max_ltv(0));
+            assert_eq!(LoanStatus::Pending, loan.get_loan_info(1).status);
+            assert!(!loan.loan_exists(2));
+            assert_eq!(loan.migrate(2, terms.clone()), Ok(true));
+            match last_event() {
+                Event::StorageMigrated(event) => {
+                    assert_eq!(0, event.from_version);
+                    assert_eq!(STORAGE_VERSION, event.to_version);
+                }
+                _ => panic!("expected StorageMigrated event"),
+            }
+            assert_eq!(STORAGE_VERSION, loan.get_storage_version());
+            let loan_info = loan.get_loan_info(3);
+            assert_eq!(LoanStatus::Active, loan_info.status);
+            assert_eq!(400, loan_info.borrowed_amount);
+            assert_eq!(600, loan_info.available_amount);
+            assert_eq!(500, loan_info.interest_rate);
+            assert_eq!(terms.maturity, loan_info.maturity);
+            assert_eq!(1000, loan_info.timestamp);
+            let schedule = loan.get_schedule(3);
+            assert_eq!(10, schedule.len());
+            assert_eq!(400, schedule[9].principal);
+            // The price the loan was given for values the collateral until the oracle updates it
+            assert_eq!(Ok(2000), loan.current_ltv(3));
+            assert_stats_match_loans(&loan);
+            assert_eq!(vec![1, 3], loan.loans_of_borrower(accounts.bob, 0, 10));
+            assert_eq!(
+                vec![1, 3],
+                loan_ids(loan.loans_by_lender(accounts.alice, 0, 10))
+            );
+            assert_eq!(vec![1, 3], loan_ids(loan.loans_by_collection(0, 0, 10)));
+            assert_eq!(loan.migrate(2, terms), Err(LoanError::NothingToMigrate));
+        }
+
+        #[ink::test]
+        fn only_roots_of_version_0_are_migrated() {
+            let accounts = default_accounts();
+            let root_v0 = RootV0 {
+                last_loan_id: 3,
+                pallet_id: accounts.frank,
+            };
+            assert_eq!(RootV0::ENCODED_SIZE as usize, root_v0.encoded_size());
+            assert_eq!(
+                scale::Encode::encode(&(3 as Id, accounts.frank)),
+                scale::Encode::encode(&root_v0)
+            );
+            let mut loan = upgraded_contract(&(root_v0, 0u8));
+            set_sender(accounts.frank);
+            assert_eq!(loan.migrate(10, terms(0)), Err(LoanError::DecodeFailed));
+            assert_eq!(loan.withdraw_credit(), Err(LoanError::MigrationPending));
+        }
+
+        fn token() -> AccountId {
+            AccountId::from([0x50; 32])
+        }
+
+        /// Prices a whole unit of the asset at a whole unit of the valuations
+        fn price_asset(loan: &mut LoanContract, asset: LoanAsset) {
+            let caller = ink::env::caller::<ink::env::DefaultEnvironment>();
+            set_sender(default_accounts().alice);
+            assert_eq!(
+                loan.update_asset_price(asset, 0, 10u128.pow(ASSET_PRICE_DECIMALS as u32)),
+                Ok(())
+            );
+            set_sender(caller);
+        }
+
+        #[ink::test]
+        fn asset_prices_are_validated() {
             let accounts = default_accounts();
             let mut loan = create_contract();
-            pay_with_call!(
-                loan.create_loan(accounts.bob, accounts.alice, 0, 0, 2000, 1000),
-                1000
+            set_timestamp(100);
+            assert_eq!(
+                loan.update_asset_price(LoanAsset::Native, 0, 1),
+                Err(LoanError::InvalidValuation)
+            );
+            assert_eq!(
+                loan.update_asset_price(LoanAsset::Psp22(token()), 0, 0),
+                Err(LoanError::InvalidValuation)
+            );
+            assert_eq!(
+                loan.update_asset_price(
+                    LoanAsset::Psp22(token()),
+                    MAX_DECIMALS - ASSET_PRICE_DECIMALS + 1,
+                    1
+                ),
+                Err(LoanError::InvalidValuation)
+            );
+            assert_eq!(
+                loan.update_asset_price(LoanAsset::Psp22(token()), 6, 500_000_000),
+                Ok(())
+            );
+            match last_event() {
+                Event::AssetPriceUpdated(event) => {
+                    assert_eq!(LoanAsset::Psp22(token()), event.asset);
+                    assert_eq!(
+                        (6, 500_000_000, 100),
+                        (event.decimals, event.price, event.timestamp)
+                    );
+                }
+                _ => panic!("expected AssetPriceUpdated event"),
+            }
+            assert_eq!(
+                Some(AssetPrice {
+                    decimals: 6,
+                    price: 500_000_000,
+                    timestamp: 100
+                }),
+                loan.get_asset_price(LoanAsset::Psp22(token()))
             );
+            assert_eq!(None, loan.get_asset_price(LoanAsset::PalletAsset(1)));
             set_sender(accounts.bob);
-            let result = loan.withdraw_funds(1, 1500);
-            assert_eq!(Err(LoanError::InsufficientLoanBalance), result);
+            assert_eq!(
+                loan.update_asset_price(LoanAsset::PalletAsset(1), 6, 1),
+                Err(LoanError::MissingRole(ORACLE))
+            );
         }
 
         #[ink::test]
-        fn repay_fails_if_amount_is_zero() {
+        fn asset_loans_value_the_collateral_in_the_smallest_unit_of_the_asset() {
             let accounts = default_accounts();
             let mut loan = create_contract();
-            let result = pay_with_call!(
-                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000),
-                1000
+            mint_collateral(&mut loan, accounts.bob, 0);
+            let asset = LoanAsset::Psp22(token());
+            crate::mock_psp22::mint(token(), accounts.alice, 10u128.pow(12));
+            crate::mock_psp22::approve(token(), accounts.alice, contract_id(), 10u128.pow(12));
+            // A loan in an asset without a price can't be checked against the collateral
+            assert_eq!(
+                loan.create_asset_loan(
+                    accounts.alice,
+                    accounts.bob,
+                    0,
+                    0,
+                    2000,
+                    1000,
+                    terms(0),
+                    asset
+                ),
+                Err(LoanError::StaleValuation)
             );
-            let contract_balance_before = ink::env::balance::<ink::env::DefaultEnvironment>();
-            let bob_balance_before =
-                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob);
-            assert_eq!(Ok(1000), bob_balance_before);
-            assert_eq!(2000, contract_balance_before);
-            set_sender(accounts.bob);
-            let result = loan.withdraw_funds(1, 500);
-            assert_eq!(Ok(()), result);
-            let repay_result = pay_with_call!(loan.repay(1, 0), 0);
+            // A whole unit of the asset with 6 decimals is worth half a unit of the valuations,
+            // so the collateral valued at 2000 is worth 4000 * 10^6 of the asset
+            assert_eq!(loan.update_asset_price(asset, 6, 500_000_000), Ok(()));
             assert_eq!(
-                Err(LoanError::RepayAmountMustBeHigherThanZero),
-                repay_result
+                loan.create_asset_loan(
+                    accounts.alice,
+                    accounts.bob,
+                    0,
+                    0,
+                    2000,
+                    2_800_000_001,
+                    terms(0),
+                    asset
+                ),
+                Err(LoanError::LtvExceeded)
+            );
+            assert_eq!(
+                loan.create_asset_loan(
+                    accounts.alice,
+                    accounts.bob,
+                    0,
+                    0,
+                    2000,
+                    2_800_000_000,
+                    terms(0),
+                    asset
+                ),
+                Ok(())
             );
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 2_000_000_000), Ok(()));
+            assert_eq!(Ok(5000), loan.current_ltv(1));
+            // The price of the asset goes stale like the valuations of the nfts
+            set_timestamp(DEFAULT_MAX_VALUATION_AGE + 1);
+            set_sender(accounts.alice);
+            assert_eq!(loan.update_valuation(0, 0, 2000), Ok(()));
+            assert_eq!(Err(LoanError::StaleValuation), loan.current_ltv(1));
+            set_sender(accounts.bob);
+            assert_eq!(loan.withdraw_funds(1, 1), Err(LoanError::StaleValuation));
         }
 
         #[ink::test]
-        fn repay_fails_not_enough_funds_provided() {
+        fn psp22_loan_moves_tokens() {
             let accounts = default_accounts();
             let mut loan = create_contract();
-            let result = pay_with_call!(
-                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000),
-                1000
+            mint_collateral(&mut loan, accounts.bob, 0);
+            // The contract shares its account with alice in the off-chain environment
+            assert_eq!(
+                AccessControl::grant_role(&mut loan, LOAN_ORIGINATOR, Some(accounts.charlie)),
+                Ok(())
             );
-            let contract_balance_before = ink::env::balance::<ink::env::DefaultEnvironment>();
-            let bob_balance_before =
-                get_account_balance::<ink::env::DefaultEnvironment>(accounts.bob);
-            assert_eq!(Ok(1000), bob_balance_before);
-            assert_eq!(2000, contract_balance_before);
+            price_asset(&mut loan, LoanAsset::Psp22(token()));
+            set_sender(accounts.charlie);
+            crate::mock_psp22::mint(token(), accounts.charlie, 2000);
+            crate::mock_psp22::approve(token(), accounts.charlie, contract_id(), 1000);
+            let contract_balance = ink::env::balance::<ink::env::DefaultEnvironment>();
+            assert_eq!(
+                loan.create_asset_loan(
+                    accounts.charlie,
+                    accounts.bob,
+                    0,
+                    0,
+                    2000,
+                    1000,
+                    terms(0),
+                    LoanAsset::Psp22(token())
+                ),
+                Ok(())
+            );
+            match last_event() {
+                Event::LoanCreated(event) => assert_eq!(LoanAsset::Psp22(token()), event.asset),
+                _ => panic!("expected LoanCreated event"),
+            }
+            assert_eq!(1000, crate::mock_psp22::balance_of(token(), contract_id()));
+            assert_eq!(
+                1000,
+                crate::mock_psp22::balance_of(token(), accounts.charlie)
+            );
+            // The amounts in tokens aren't mixed into the aggregates of the native loans
+            let stats = loan.portfolio_stats(LoanAsset::Psp22(token()));
+            assert_eq!(1000, stats.total_committed);
+            assert_eq!(1, stats.loans_in(LoanStatus::Pending));
+            assert_eq!(
+                PortfolioStats::default(),
+                loan.portfolio_stats(LoanAsset::Native)
+            );
+            assert_eq!(
+                pay_with_call!(loan.update_loan(1, 200), 0),
+                Err(LoanError::AssetTransferFailed)
+            );
+            crate::mock_psp22::approve(token(), accounts.charlie, contract_id(), 200);
+            assert_eq!(pay_with_call!(loan.update_loan(1, 200), 0), Ok(()));
             set_sender(accounts.bob);
-            let result = loan.withdraw_funds(1, 500);
-            assert_eq!(Ok(()), result);
-            let repay_result = pay_with_call!(loan.repay(1, 500), 400);
-            assert_eq!(Err(LoanError::NotEnoughFundsProvided), repay_result);
+            assert_eq!(loan.withdraw_funds(1, 400), Ok(()));
+            assert_eq!(400, crate::mock_psp22::balance_of(token(), accounts.bob));
+            assert_eq!(800, crate::mock_psp22::balance_of(token(), contract_id()));
+            assert_eq!(
+                contract_balance,
+                ink::env::balance::<ink::env::DefaultEnvironment>()
+            );
+            assert_stats_match_loans(&loan);
         }
 
         #[ink::test]
-        fn charge_apy_works() {
+        fn upgrade_requires_owner_and_migrate_admin_role() {
             let accounts = default_accounts();
             let mut loan = create_contract();
-            pay_with_call!(
-                loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000),
-                1000
+            assert_eq!(Some(accounts.alice), Ownable::owner(&loan));
+            assert_eq!(loan.migrate(10, terms(0)), Err(LoanError::NothingToMigrate));
+            set_sender(accounts.bob);
+            assert_eq!(loan.upgrade([1; 32]), Err(LoanError::CallerIsNotOwner));
+            assert_eq!(
+                loan.migrate(10, terms(0)),
+                Err(LoanError::MissingRole(ADMIN))
             );
-            let loan_info_before = loan.get_loan_info(1);
-            assert_eq!(0, loan_info_before.borrowed_amount);
-            let result = loan.charge_apy(1, 100);
-            assert_eq!(Ok(()), result);
-            let loan_info_after = loan.get_loan_info(1);
-            assert_eq!(100, loan_info_after.borrowed_amount);
+        }
+
+        #[cfg(feature = "mock-runtime")]
+        mod runtime_calls {
+            use super::*;
+            use crate::mock_runtime;
+
+            fn create_active_loan() -> LoanContract {
+                let accounts = default_accounts();
+                let mut loan = create_contract();
+                mint_collateral(&mut loan, accounts.bob, 0);
+                pay_with_call!(
+                    loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                    1000
+                )
+                .unwrap();
+                set_sender(accounts.bob);
+                assert_eq!(Ok(()), loan.withdraw_funds(1, 500));
+                loan
+            }
+
+            /// Returns the contract upgraded from version 0 and migrated with terms starting at 1000
+            fn migrated_from_version_0() -> LoanContract {
+                let accounts = default_accounts();
+                let mut loan = upgraded_from_version_0();
+                set_timestamp(1000);
+                // Version 0 had no roles, the community-loan-pool account takes over
+                assert_eq!(
+                    loan.migrate(3, terms(500)),
+                    Err(LoanError::MissingRole(ADMIN))
+                );
+                set_sender(accounts.frank);
+                assert_eq!(loan.migrate(3, terms(500)), Ok(true));
+                loan
+            }
+
+            #[ink::test]
+            fn migrated_loans_of_version_0_leave_the_collateral_where_it_is() {
+                let accounts = default_accounts();
+                let mut loan = migrated_from_version_0();
+                // Version 0 scaled the loan amounts by 10^12
+                set_balance(contract_id(), 2000 * 10u128.pow(12));
+                set_balance(accounts.bob, 400 * 10u128.pow(12));
+                assert_eq!(
+                    AccessControl::grant_role(&mut loan, LOAN_ORIGINATOR, Some(accounts.alice)),
+                    Ok(())
+                );
+                set_sender(accounts.alice);
+                assert_eq!(loan.delete_loan(1), Ok(()));
+                set_sender(accounts.bob);
+                assert_eq!(
+                    pay_with_call!(loan.repay(3, 400), 400 * 10u128.pow(12)),
+                    Ok(())
+                );
+                assert_eq!(LoanStatus::Repaid, loan.get_loan_info(3).status);
+                // Version 0 never took the nfts into escrow, so there is nothing to release
+                assert!(!last_events(usize::MAX)
+                    .iter()
+                    .any(|event| matches!(event, Event::CollateralReleased(_))));
+                assert_stats_match_loans(&loan);
+            }
+
+            #[ink::test]
+            fn delete_loan_dispatches_delete_call() {
+                let accounts = default_accounts();
+                let mut loan = create_contract();
+                mint_collateral(&mut loan, accounts.bob, 0);
+                pay_with_call!(
+                    loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                    1000
+                )
+                .unwrap();
+                let pallet_balance =
+                    get_account_balance::<ink::env::DefaultEnvironment>(accounts.frank).unwrap();
+                assert_eq!(Ok(()), loan.delete_loan(1));
+                assert_eq!(
+                    vec![encoded_call(
+                        RuntimeCallIndices::default(),
+                        CommunityLoanPoolCall::DeleteLoan { loan_id: 1 }
+                    )],
+                    mock_runtime::calls()
+                );
+                assert_eq!(
+                    Ok(pallet_balance + 1000),
+                    get_account_balance::<ink::env::DefaultEnvironment>(accounts.frank)
+                );
+                assert_eq!(
+                    Some(accounts.bob),
+                    crate::mock_nft::owner_of(nft_contract(), 0)
+                );
+                assert!(!loan.loan_exists(1));
+                assert!(loan.loans_by_borrower(accounts.bob, 0, 10).is_empty());
+                assert!(loan.loans_by_lender(accounts.alice, 0, 10).is_empty());
+                assert!(loan.loans_by_collection(0, 0, 10).is_empty());
+                assert_eq!(
+                    PortfolioStats::default(),
+                    loan.portfolio_stats(LoanAsset::Native)
+                );
+            }
+
+            #[ink::test]
+            fn delete_loan_emits_event() {
+                let accounts = default_accounts();
+                let mut loan = create_contract();
+                mint_collateral(&mut loan, accounts.bob, 0);
+                pay_with_call!(
+                    loan.create_loan(accounts.alice, accounts.bob, 0, 0, 2000, 1000, terms(0)),
+                    1000
+                )
+                .unwrap();
+                assert_eq!(Ok(()), loan.delete_loan(1));
+                match last_event() {
+                    Event::LoanDeleted(event) => {
+                        assert_eq!(1, event.loan_id);
+                        assert_eq!(accounts.bob, event.borrower);
+                        assert_eq!(accounts.alice, event.lender);
+                        assert_eq!(1000, event.returned_amount);
+                    }
+                    _ => panic!("expected LoanDeleted event"),
+                }
+            }
+
+            #[ink::test]
+            fn transfer_loan_fails_once_the_loan_is_repaid() {
+                let accounts = default_accounts();
+                let mut loan = create_active_loan();
+                assert_eq!(pay_with_call!(loan.repay(1, 500), 500), Ok(()));
+                set_sender(accounts.alice);
+                assert_eq!(
+                    AccessControl::grant_role(&mut loan, LOAN_ORIGINATOR, Some(accounts.charlie)),
+                    Ok(())
+                );
+                assert_eq!(
+                    loan.transfer_loan(1, accounts.charlie),
+                    Err(LoanError::InvalidLoanStatus(LoanStatus::Repaid))
+                );
+                assert_eq!(accounts.alice, loan.get_loan_info(1).lender);
+            }
+
+            #[ink::test]
+            fn repay_emits_event() {
+                let accounts = default_accounts();
+                let mut loan = create_active_loan();
+                assert_eq!(pay_with_call!(loan.repay(1, 200), 200), Ok(()));
+                match last_event() {
+                    Event::LoanRepaid(event) => {
+                        assert_eq!(1, event.loan_id);
+                        assert_eq!(accounts.bob, event.borrower);
+                        assert_eq!(accounts.alice, event.lender);
+                        assert_eq!(200, event.repay_amount);
+                        assert_eq!(300, event.borrowed_amount);
+                    }
+                    _ => panic!("expected LoanRepaid event"),
+                }
+            }
+
+            #[ink::test]
+            fn repay_dispatches_update_call() {
+                let accounts = default_accounts();
+                let mut loan = create_active_loan();
+                assert_eq!(pay_with_call!(loan.repay(1, 200), 200), Ok(()));
+                assert_eq!(300, loan.get_loan_info(1).borrowed_amount);
+                assert_eq!(pay_with_call!(loan.repay(1, 300), 300), Ok(()));
+                assert_eq!(
+                    vec![
+                        encoded_call(
+                            RuntimeCallIndices::default(),
+                            CommunityLoanPoolCall::UpdateLoan {
+                                loan_id: 1,
+                                amount: 200
+                            }
+                        ),
+                        encoded_call(
+                            RuntimeCallIndices::default(),
+                            CommunityLoanPoolCall::UpdateLoan {
+                                loan_id: 1,
+                                amount: 300
+                            }
+                        ),
+                    ],
+                    mock_runtime::calls()
+                );
+                let loan_info = loan.get_loan_info(1);
+                assert_eq!(0, loan_info.borrowed_amount);
+                assert_eq!(LoanStatus::Repaid, loan_info.status);
+                let stats = loan.portfolio_stats(LoanAsset::Native);
+                assert_eq!(500, stats.total_repaid);
+                assert_eq!(0, stats.total_outstanding);
+                assert_eq!(1, stats.loans_in(LoanStatus::Repaid));
+                assert_stats_match_loans(&loan);
+                assert_eq!(
+                    Some(accounts.bob),
+                    crate::mock_nft::owner_of(nft_contract(), 0)
+                );
+            }
+
+            #[ink::test]
+            fn liquidate_dispatches_liquidate_call() {
+                let accounts = default_accounts();
+                let mut loan = create_active_loan();
+                set_sender(accounts.alice);
+                assert_eq!(
+                    AccessControl::grant_role(&mut loan, LIQUIDATOR, Some(accounts.alice)),
+                    Ok(())
+                );
+                // The oracle values the collateral below the debt
+                assert_eq!(loan.update_valuation(0, 0, 300), Ok(()));
+                let pallet_balance =
+                    get_account_balance::<ink::env::DefaultEnvironment>(accounts.frank).unwrap();
+                assert_eq!(Ok(()), loan.liquidate(1));
+                assert_eq!(
+                    vec![encoded_call(
+                        RuntimeCallIndices::default(),
+                        CommunityLoanPoolCall::LiquidateLoan {
+                            loan_id: 1,
+                            shortfall: 200
+                        }
+                    )],
+                    mock_runtime::calls()
+                );
+                match last_event() {
+                    Event::LoanLiquidated(event) => {
+                        assert_eq!(500, event.debt);
+                        assert_eq!(200, event.shortfall);
+                        assert_eq!(500, event.returned_amount);
+                    }
+                    _ => panic!("expected LoanLiquidated event"),
+                }
+                // The undrawn funds are returned and no longer committed to the loan
+                assert_eq!(
+                    Ok(pallet_balance + 500),
+                    get_account_balance::<ink::env::DefaultEnvironment>(accounts.frank)
+                );
+                let loan_info = loan.get_loan_info(1);
+                assert_eq!(LoanStatus::Closed, loan_info.status);
+                assert_eq!(0, loan_info.available_amount);
+                assert_eq!(0, loan.portfolio_stats(LoanAsset::Native).total_committed);
+                assert_eq!(
+                    1,
+                    loan.portfolio_stats(LoanAsset::Native)
+                        .loans_in(LoanStatus::Closed)
+                );
+                assert_stats_match_loans(&loan);
+                assert_eq!(
+                    Some(accounts.alice),
+                    crate::mock_nft::owner_of(nft_contract(), 0)
+                );
+            }
+
+            #[ink::test]
+            fn liquidated_loans_only_take_their_own_funding() {
+                let accounts = default_accounts();
+                let mut loan = create_contract();
+                for item_id in 0..2 {
+                    mint_collateral(&mut loan, accounts.bob, item_id);
+                    pay_with_call!(
+                        loan.create_loan(
+                            accounts.alice,
+                            accounts.bob,
+                            0,
+                            item_id,
+                            2000,
+                            1000,
+                            terms(0)
+                        ),
+                        1000
+                    )
+                    .unwrap();
+                }
+                assert_eq!(
+                    AccessControl::grant_role(&mut loan, LIQUIDATOR, Some(accounts.alice)),
+                    Ok(())
+                );
+                // The balance of the contract falls short of the funds of both loans
+                set_balance(contract_id(), 1500);
+                set_sender(accounts.bob);
+                assert_eq!(loan.withdraw_funds(1, 500), Ok(()));
+                set_sender(accounts.alice);
+                assert_eq!(loan.update_valuation(0, 0, 300), Ok(()));
+                assert_eq!(loan.liquidate(1), Ok(()));
+                // The undrawn funds of the liquidated loan are returned and no longer committed
+                assert_eq!(
+                    Ok(500),
+                    get_account_balance::<ink::env::DefaultEnvironment>(contract_id())
+                );
+                assert_eq!(1000, loan.native_committed.get_or_default());
+                assert_stats_match_loans(&loan);
+                let report = loan.check_invariants().unwrap();
+                assert_eq!((1000, 500), (report.committed, report.deficit));
+                set_sender(accounts.bob);
+                assert_eq!(
+                    loan.withdraw_funds(2, 501),
+                    Err(LoanError::InsufficientLoanBalance)
+                );
+                assert_eq!(loan.withdraw_funds(2, 500), Ok(()));
+            }
+
+            #[ink::test]
+            fn liquidate_requires_a_current_valuation_for_the_shortfall() {
+                let accounts = default_accounts();
+                let mut loan = create_active_loan();
+                set_sender(accounts.alice);
+                assert_eq!(
+                    AccessControl::grant_role(&mut loan, LIQUIDATOR, Some(accounts.alice)),
+                    Ok(())
+                );
+                set_timestamp(11 * MILLISECONDS_PER_YEAR);
+                assert_eq!(loan.liquidate(1), Err(LoanError::StaleValuation));
+                assert_eq!(loan.update_valuation(0, 0, 2000), Ok(()));
+                assert_eq!(Ok(()), loan.liquidate(1));
+                assert_eq!(
+                    Some(0),
+                    loan.get_liquidation(1)
+                        .map(|liquidation| liquidation.shortfall)
+                );
+            }
+
+            #[ink::test]
+            fn repay_applies_the_payment_waterfall() {
+                let accounts = default_accounts();
+                let mut loan = create_active_loan();
+                set_sender(accounts.alice);
+                assert_eq!(loan.add_charge(1, Charge::Fee, 20), Ok(()));
+                assert_eq!(loan.add_charge(1, Charge::Penalty, 30), Ok(()));
+                set_sender(accounts.bob);
+                assert_eq!(pay_with_call!(loan.repay(1, 100), 100), Ok(()));
+                match last_event() {
+                    Event::LoanRepaid(event) => {
+                        assert_eq!(
+                            Debt {
+                                principal: 50,
+                                interest: 0,
+                                fees: 20,
+                                penalties: 30,
+                            },
+                            event.paid
+                        );
+                        assert_eq!(450, event.borrowed_amount);
+                    }
+                    _ => panic!("expected LoanRepaid event"),
+                }
+                assert_eq!(Debt::from_principal(450), loan.get_loan_info(1).debt);
+                // Only the principal counts towards the installments
+                assert_eq!(50, loan.get_schedule(1)[9].paid);
+                set_sender(accounts.alice);
+                assert_eq!(loan.add_charge(1, Charge::Fee, 20), Ok(()));
+                let principal_first = vec![
+                    PaymentComponent::Principal,
+                    PaymentComponent::Interest,
+                    PaymentComponent::Fees,
+                    PaymentComponent::Penalties,
+                ];
+                assert_eq!(loan.set_payment_waterfall(principal_first), Ok(()));
+                set_sender(accounts.bob);
+                assert_eq!(pay_with_call!(loan.repay(1, 460), 460), Ok(()));
+                assert_eq!(
+                    Debt {
+                        fees: 10,
+                        ..Default::default()
+                    },
+                    loan.get_loan_info(1).debt
+                );
+                assert_eq!(LoanStatus::Active, loan.get_loan_info(1).status);
+            }
+
+            #[ink::test]
+            fn calls_use_configured_indices() {
+                let accounts = default_accounts();
+                let mut loan = create_active_loan();
+                set_sender(accounts.alice);
+                let indices = RuntimeCallIndices {
+                    community_loan_pool: 42,
+                    delete_loan: 0,
+                    update_loan: 1,
+                    liquidate_loan: 2,
+                    ..Default::default()
+                };
+                assert_eq!(loan.set_call_indices(indices), Ok(()));
+                set_sender(accounts.bob);
+                assert_eq!(pay_with_call!(loan.repay(1, 100), 100), Ok(()));
+                assert_eq!(
+                    vec![encoded_call(
+                        indices,
+                        CommunityLoanPoolCall::UpdateLoan {
+                            loan_id: 1,
+                            amount: 100
+                        }
+                    )],
+                    mock_runtime::calls()
+                );
+            }
+
+            #[ink::test]
+            fn repay_is_accepted_while_paused() {
+                let accounts = default_accounts();
+                let mut loan = create_active_loan();
+                set_sender(accounts.alice);
+                assert_eq!(
+                    AccessControl::grant_role(&mut loan, PAUSER, Some(accounts.alice)),
+                    Ok(())
+                );
+                assert_eq!(loan.pause(PauseScope::All), Ok(()));
+                assert_eq!(loan.pause(PauseScope::Loan(1)), Ok(()));
+                set_sender(accounts.bob);
+                assert_eq!(pay_with_call!(loan.repay(1, 500), 500), Ok(()));
+                assert_eq!(LoanStatus::Repaid, loan.get_loan_info(1).status);
+            }
+
+            #[ink::test]
+            fn psp22_loan_is_repaid_and_returned_in_tokens() {
+                let accounts = default_accounts();
+                let mut loan = create_contract();
+                mint_collateral(&mut loan, accounts.bob, 0);
+                assert_eq!(
+                    AccessControl::grant_role(&mut loan, LOAN_ORIGINATOR, Some(accounts.charlie)),
+                    Ok(())
+                );
+                price_asset(&mut loan, LoanAsset::Psp22(token()));
+                set_sender(accounts.charlie);
+                crate::mock_psp22::mint(token(), accounts.charlie, 1000);
+                crate::mock_psp22::approve(token(), accounts.charlie, contract_id(), 1000);
+                assert_eq!(
+                    loan.create_asset_loan(
+                        accounts.charlie,
+                        accounts.bob,
+                        0,
+                        0,
+                        2000,
+                        1000,
+                        terms(0),
+                        LoanAsset::Psp22(token())
+                    ),
+                    Ok(())
+                );
+                set_sender(accounts.bob);
+                assert_eq!(loan.withdraw_funds(1, 400), Ok(()));
+                crate::mock_psp22::approve(token(), accounts.bob, contract_id(), 400);
+                assert_eq!(loan.repay(1, 400), Ok(()));
+                assert_eq!(0, crate::mock_psp22::balance_of(token(), accounts.bob));
+                assert_eq!(400, crate::mock_psp22::balance_of(token(), accounts.frank));
+                set_sender(accounts.charlie);
+                assert_eq!(loan.delete_loan(1), Ok(()));
+                assert_eq!(1000, crate::mock_psp22::balance_of(token(), accounts.frank));
+                assert_eq!(0, crate::mock_psp22::balance_of(token(), contract_id()));
+            }
+
+            #[ink::test]
+            fn pallet_asset_loan_dispatches_asset_transfers() {
+                let accounts = default_accounts();
+                let mut loan = create_contract();
+                mint_collateral(&mut loan, accounts.bob, 0);
+                price_asset(&mut loan, LoanAsset::PalletAsset(1));
+                assert_eq!(
+                    loan.create_asset_loan(
+                        accounts.alice,
+                        accounts.bob,
+                        0,
+                        0,
+                        2000,
+                        1000,
+                        terms(0),
+                        LoanAsset::PalletAsset(1)
+                    ),
+                    Ok(())
+                );
+                set_sender(accounts.bob);
+                assert_eq!(loan.withdraw_funds(1, 400), Ok(()));
+                let indices = RuntimeCallIndices::default();
+                assert_eq!(
+                    vec![
+                        encoded_call(
+                            indices,
+                            AssetsCall::TransferApproved {
+                                id: 1,
+                                owner: accounts.alice,
+                                destination: contract_id(),
+                                amount: 1000
+                            }
+                        ),
+                        encoded_call(
+                            indices,
+                            AssetsCall::Transfer {
+                                id: 1,
+                                target: accounts.bob,
+                                amount: 400
+                            }
+                        ),
+                    ],
+                    mock_runtime::calls()
+                );
+            }
+
+            #[ink::test]
+            fn pallet_failures_return_call_runtime_failed() {
+                let accounts = default_accounts();
+                let mut loan = create_active_loan();
+                let loan_info = loan.get_loan_info(1);
+                let stats = loan.portfolio_stats(LoanAsset::Native);
+                let schedule = loan.get_schedule(1);
+                let pallet_balance =
+                    get_account_balance::<ink::env::DefaultEnvironment>(accounts.frank);
+                mock_runtime::set_failing(true);
+                assert_eq!(
+                    pay_with_call!(loan.repay(1, 100), 100),
+                    Err(LoanError::CallRuntimeFailed)
+                );
+                assert!(mock_runtime::calls().is_empty());
+                // Nothing has been stored or paid out before the call failed
+                assert_eq!(loan_info, loan.get_loan_info(1));
+                assert_eq!(stats, loan.portfolio_stats(LoanAsset::Native));
+                assert_eq!(schedule, loan.get_schedule(1));
+                assert_eq!(
+                    pallet_balance,
+                    get_account_balance::<ink::env::DefaultEnvironment>(accounts.frank)
+                );
+                mock_runtime::set_failing(false);
+                assert_eq!(pay_with_call!(loan.repay(1, 100), 100), Ok(()));
+                assert_eq!(1, mock_runtime::calls().len());
+            }
+        }
+    }
+
+    #[cfg(all(test, feature = "e2e-tests", feature = "permissive-node"))]
+    mod e2e_tests {
+        use super::*;
+        use collateral_nft::collateral_nft::CollateralNftRef;
+        use ink_e2e::{build_message, subxt::blocks::ExtrinsicEvents, AccountKeyring};
+        use openbrush::contracts::psp34::{extensions::mintable::PSP34Mintable, Id, PSP34};
+
+        type E2EResult<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+        type Event = <LoanContract as ::ink::reflect::ContractEventBase>::Type;
+
+        /// Decodes the events the contracts emitted during an extrinsic
+        fn contract_events<C: ink_e2e::subxt::Config>(events: &ExtrinsicEvents<C>) -> Vec<Event> {
+            events
+                .iter()
+                .filter_map(|event| {
+                    event
+                        .ok()?
+                        .as_event::<ink_e2e::ContractEmitted<ink::env::DefaultEnvironment>>()
+                        .ok()
+                        .flatten()
+                })
+                .filter_map(|emitted| <Event as scale::Decode>::decode(&mut &emitted.data[..]).ok())
+                .collect()
+        }
+
+        #[ink_e2e::test(additional_contracts = "../collateral_nft/Cargo.toml")]
+        async fn loan_lifecycle_works(mut client: ink_e2e::Client<C, E>) -> E2EResult<()> {
+            let alice = ink_e2e::account_id(AccountKeyring::Alice);
+            let bob = ink_e2e::account_id(AccountKeyring::Bob);
+            let pallet_id = ink_e2e::account_id(AccountKeyring::Ferdie);
+
+            let nft_contract = client
+                .instantiate(
+                    "collateral_nft",
+                    &ink_e2e::alice(),
+                    CollateralNftRef::new(),
+                    0,
+                    None,
+                )
+                .await
+                .expect("instantiating the collection failed")
+                .account_id;
+            let loan_contract = client
+                .instantiate(
+                    "loan",
+                    &ink_e2e::alice(),
+                    LoanContractRef::new(pallet_id, 0),
+                    0,
+                    None,
+                )
+                .await
+                .expect("instantiating the loan contract failed")
+                .account_id;
+
+            // Bob owns the collateral and approves the loan contract to take it into escrow
+            let mint = build_message::<CollateralNftRef>(nft_contract)
+                .call(|nft| nft.mint(bob, Id::U32(0)));
+            client
+                .call(&ink_e2e::alice(), mint, 0, None)
+                .await
+                .expect("mint failed");
+            let approve = build_message::<CollateralNftRef>(nft_contract)
+                .call(|nft| nft.approve(loan_contract, Some(Id::U32(0)), true));
+            client
+                .call(&ink_e2e::bob(), approve, 0, None)
+                .await
+                .expect("approve failed");
+
+            let register_collection = build_message::<LoanContractRef>(loan_contract)
+                .call(|loan| loan.register_collection(0, nft_contract));
+            client
+                .call(&ink_e2e::alice(), register_collection, 0, None)
+                .await
+                .expect("register_collection failed");
+            for role in [LOAN_ORIGINATOR, INTEREST_KEEPER, ORACLE] {
+                let grant_role = build_message::<LoanContractRef>(loan_contract)
+                    .call(|loan| loan.grant_role(role, Some(alice)));
+                client
+                    .call(&ink_e2e::alice(), grant_role, 0, None)
+                    .await
+                    .expect("grant_role failed");
+            }
+            let update_valuation = build_message::<LoanContractRef>(loan_contract)
+                .call(|loan| loan.update_valuation(0, 0, 2_000_000));
+            client
+                .call(&ink_e2e::alice(), update_valuation, 0, None)
+                .await
+                .expect("update_valuation failed");
+
+            // Create
+            let contract_balance = client.balance(loan_contract).await?;
+            let terms = LoanTerms {
+                interest_rate: 1000,
+                maturity: 10 * MILLISECONDS_PER_YEAR,
+                payment_frequency: MILLISECONDS_PER_YEAR,
+                repayment_type: RepaymentType::InterestOnly,
+            };
+            let create_loan = build_message::<LoanContractRef>(loan_contract)
+                .call(|loan| loan.create_loan(alice, bob, 0, 0, 2_000_000, 1_000_000, terms));
+            let result = client
+                .call(&ink_e2e::alice(), create_loan, 1_000_000, None)
+                .await
+                .expect("create_loan failed");
+            assert_eq!(Ok(()), result.return_value());
+            assert!(contract_events(&result.events)
+                .iter()
+                .any(|event| matches!(event, Event::LoanCreated(event) if event.loan_id == 1)));
+            assert_eq!(
+                contract_balance + 1_000_000,
+                client.balance(loan_contract).await?
+            );
+            let owner_of = build_message::<CollateralNftRef>(nft_contract)
+                .call(|nft| nft.owner_of(Id::U32(0)));
+            let owner = client
+                .call_dry_run(&ink_e2e::alice(), &owner_of, 0, None)
+                .await
+                .return_value();
+            assert_eq!(Some(loan_contract), owner);
+
+            // Withdraw
+            let withdraw_funds = build_message::<LoanContractRef>(loan_contract)
+                .call(|loan| loan.withdraw_funds(1, 400_000));
+            let result = client
+                .call(&ink_e2e::bob(), withdraw_funds, 0, None)
+                .await
+                .expect("withdraw_funds failed");
+            assert_eq!(Ok(()), result.return_value());
+            assert!(contract_events(&result.events).iter().any(
+                |event| matches!(event, Event::FundsWithdrawn(event) if event.amount == 400_000)
+            ));
+            assert_eq!(
+                contract_balance + 600_000,
+                client.balance(loan_contract).await?
+            );
+
+            // Charge
+            let charge_apy =
+                build_message::<LoanContractRef>(loan_contract).call(|loan| loan.charge_apy(1));
+            let result = client
+                .call(&ink_e2e::alice(), charge_apy, 0, None)
+                .await
+                .expect("charge_apy failed");
+            assert_eq!(Ok(()), result.return_value());
+            assert!(contract_events(&result.events)
+                .iter()
+                .any(|event| matches!(event, Event::InterestCharged(_))));
+            let get_loan_info = build_message::<LoanContractRef>(loan_contract)
+                .call(|loan| loan.try_get_loan_info(1));
+            let loan_info = client
+                .call_dry_run(&ink_e2e::alice(), &get_loan_info, 0, None)
+                .await
+                .return_value()
+                .expect("loan does not exist");
+            assert!(loan_info.borrowed_amount >= 400_000);
+            assert_eq!(LoanStatus::Active, loan_info.status);
+
+            let owner = client
+                .call_dry_run(&ink_e2e::alice(), &owner_of, 0, None)
+                .await
+                .return_value();
+            assert_eq!(Some(loan_contract), owner);
+
+            // Repay
+            let debt = loan_info.borrowed_amount;
+            let bob_balance = client.balance(bob).await?;
+            let pallet_balance = client.balance(pallet_id).await?;
+            let repay =
+                build_message::<LoanContractRef>(loan_contract).call(|loan| loan.repay(1, debt));
+            let result = client
+                .call(&ink_e2e::bob(), repay, debt, None)
+                .await
+                .expect("repay failed");
+            assert_eq!(Ok(()), result.return_value());
+            assert!(contract_events(&result.events).iter().any(|event| matches!(
+                event,
+                Event::LoanRepaid(event) if event.repay_amount == debt && event.borrowed_amount == 0
+            )));
+            // The borrower pays the transaction fees on top of the debt
+            assert!(client.balance(bob).await? <= bob_balance - debt);
+            assert_eq!(pallet_balance + debt, client.balance(pallet_id).await?);
+            let owner = client
+                .call_dry_run(&ink_e2e::alice(), &owner_of, 0, None)
+                .await
+                .return_value();
+            assert_eq!(Some(bob), owner);
+
+            // Delete
+            let delete_loan =
+                build_message::<LoanContractRef>(loan_contract).call(|loan| loan.delete_loan(1));
+            let result = client
+                .call(&ink_e2e::alice(), delete_loan, 0, None)
+                .await
+                .expect("delete_loan failed");
+            assert_eq!(Ok(()), result.return_value());
+            assert!(contract_events(&result.events).iter().any(|event| matches!(
+                event,
+                Event::LoanDeleted(event) if event.returned_amount == 600_000
+            )));
+            assert_eq!(
+                pallet_balance + debt + 600_000,
+                client.balance(pallet_id).await?
+            );
+            assert_eq!(contract_balance, client.balance(loan_contract).await?);
+            let owner = client
+                .call_dry_run(&ink_e2e::alice(), &owner_of, 0, None)
+                .await
+                .return_value();
+            assert_eq!(Some(bob), owner);
+            let loan_exists =
+                build_message::<LoanContractRef>(loan_contract).call(|loan| loan.loan_exists(1));
+            assert!(!client
+                .call_dry_run(&ink_e2e::alice(), &loan_exists, 0, None)
+                .await
+                .return_value());
+
+            Ok(())
         }
     }
 }
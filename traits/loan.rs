@@ -1,11 +1,56 @@
-use openbrush::traits::{AccountId, Balance, Timestamp};
+use ink::prelude::vec::Vec;
+use openbrush::{
+    contracts::access_control::{RoleType, DEFAULT_ADMIN_ROLE},
+    traits::{AccountId, Balance, Timestamp},
+};
 
 type Id = u32;
 
+/// Denominator of the interest rate, rates are expressed in basis points
+pub const BASIS_POINTS: u32 = 10_000;
+/// Length of a year in milliseconds, used to pro-rate the annual interest rate
+pub const MILLISECONDS_PER_YEAR: Timestamp = 365 * 24 * 60 * 60 * 1000;
+/// Scale of the fractional interest carried between two accruals
+pub const INTEREST_PRECISION: Balance = BASIS_POINTS as Balance * MILLISECONDS_PER_YEAR as Balance;
+
+/// Maximum loan to value ratio in basis points for new loans and drawdowns
+/// unless the admin sets another one for the contract or a collection
+pub const DEFAULT_MAX_LTV: u32 = 7_000;
+
+/// Version of the query messages, increased whenever their signatures change
+pub const QUERY_VERSION: u32 = 1;
+
+/// Version of the storage layout, increased whenever stored loans have to be migrated after an upgrade
+/// Version 0 is the layout of the first release, whose loans had no interest rate, status, terms
+/// or asset and which kept the settings of the contract in its root instead of lazy cells
+pub const STORAGE_VERSION: u32 = 1;
+
+/// Highest number of loans that are returned by a single index query
+pub const MAX_LOAN_PAGE: u32 = 100;
+
+/// Highest number of installments a loan can be paid back with
+pub const MAX_INSTALLMENTS: u32 = 360;
+
+/// Highest number of decimals whose scaling factor fits into a Balance
+pub const MAX_DECIMALS: u8 = 38;
+
+/// Role that administrates all other roles of the contract
+pub const ADMIN: RoleType = DEFAULT_ADMIN_ROLE;
+/// Role that is allowed to create new loans
+pub const LOAN_ORIGINATOR: RoleType = ink::selector_id!("LOAN_ORIGINATOR");
+/// Role that is allowed to charge the accrued interest, fees and penalties to the loans
+pub const INTEREST_KEEPER: RoleType = ink::selector_id!("INTEREST_KEEPER");
+/// Role that is allowed to liquidate loans and distribute the recovered proceeds
+pub const LIQUIDATOR: RoleType = ink::selector_id!("LIQUIDATOR");
+/// Role that is allowed to pause the contract
+pub const PAUSER: RoleType = ink::selector_id!("PAUSER");
+/// Role that is allowed to check the solvency of the contract besides the admin
+pub const AUDITOR: RoleType = ink::selector_id!("AUDITOR");
+
 #[cfg(feature = "std")]
 use ink::storage::traits::StorageLayout;
 
-#[derive(Debug, Clone, scale::Encode, scale::Decode)]
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
 #[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
 pub struct LoanInfo {
     /// Admin who calls the create_loan function
@@ -16,13 +61,31 @@ pub struct LoanInfo {
     pub collection_id: u32,
     /// Item ID of the nft
     pub item_id: u32,
-    /// Collateral price of the nft
+    /// Collateral price of the nft given at creation, deprecated and only kept for reference
     pub collateral_price: Balance,
+    /// Asset the loan is funded, drawn and repaid in
+    pub asset: LoanAsset,
     /// Available amount of funds for the borrower to borrow
     pub available_amount: Balance,
-    /// Actual amount of funds that has been borrowed
+    /// Amount the borrower owes, the sum of the parts in `debt`
     pub borrowed_amount: Balance,
-    /// Timestamp when the loan has been created or from the latest update
+    /// Parts of the borrowed amount that are still owed
+    pub debt: Debt,
+    /// Annual interest rate of the loan in basis points
+    pub interest_rate: u32,
+    /// Fraction of a unit of interest that has not been charged yet, scaled by INTEREST_PRECISION
+    pub interest_remainder: Balance,
+    /// Current status of the loan
+    pub status: LoanStatus,
+    /// How the loan is paid back over its term
+    pub repayment_type: RepaymentType,
+    /// Time between two installments in milliseconds
+    pub payment_frequency: Timestamp,
+    /// Timestamp when the loan has been created
+    pub start_date: Timestamp,
+    /// Timestamp when the loan has to be paid back in full
+    pub maturity: Timestamp,
+    /// Timestamp when the loan has been created or interest has been accrued the last time
     pub timestamp: Timestamp,
 }
 
@@ -34,20 +97,564 @@ impl Default for LoanInfo {
             collection_id: Default::default(),
             item_id: Default::default(),
             collateral_price: Balance::default(),
+            asset: LoanAsset::Native,
             available_amount: Balance::default(),
             borrowed_amount: Balance::default(),
+            debt: Debt::default(),
+            interest_rate: Default::default(),
+            interest_remainder: Balance::default(),
+            status: LoanStatus::Pending,
+            repayment_type: RepaymentType::Amortizing,
+            payment_frequency: Timestamp::default(),
+            start_date: Timestamp::default(),
+            maturity: Timestamp::default(),
             timestamp: Timestamp::default(),
         }
     }
 }
 
+/// Layout of a loan in storage version 0, the fields mean the same as in LoanInfo
+#[derive(Debug, Clone, scale::Encode, scale::Decode)]
+pub struct LoanInfoV0 {
+    pub lender: AccountId,
+    pub borrower: AccountId,
+    pub collection_id: u32,
+    pub item_id: u32,
+    pub collateral_price: Balance,
+    pub available_amount: Balance,
+    pub borrowed_amount: Balance,
+    pub timestamp: Timestamp,
+}
+
+impl LoanInfoV0 {
+    /// Converts the loan to the current layout with the terms it is paid back with from now on
+    pub fn migrate(self, terms: &LoanTerms, timestamp: Timestamp) -> LoanInfo {
+        let status = if self.borrowed_amount > 0 {
+            LoanStatus::Active
+        } else {
+            LoanStatus::Pending
+        };
+        LoanInfo {
+            lender: self.lender,
+            borrower: self.borrower,
+            collection_id: self.collection_id,
+            item_id: self.item_id,
+            collateral_price: self.collateral_price,
+            asset: LoanAsset::Native,
+            available_amount: self.available_amount,
+            borrowed_amount: self.borrowed_amount,
+            debt: Debt::from_principal(self.borrowed_amount),
+            interest_rate: terms.interest_rate,
+            interest_remainder: 0,
+            status,
+            repayment_type: terms.repayment_type,
+            payment_frequency: terms.payment_frequency,
+            start_date: timestamp,
+            maturity: terms.maturity,
+            timestamp,
+        }
+    }
+}
+
+/// Packed fields of the contract root in storage version 0, which kept them in the root
+/// instead of lazy cells
+#[derive(Debug, Clone, scale::Encode, scale::Decode)]
+pub struct RootV0 {
+    pub last_loan_id: u32,
+    pub pallet_id: AccountId,
+}
+
+impl RootV0 {
+    /// Size of the encoded root, a root of any other size isn't one of version 0
+    pub const ENCODED_SIZE: u32 = 36;
+}
+
+/// Parts of the debt of a loan, which add up to its borrowed amount
+#[derive(Debug, Default, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub struct Debt {
+    /// Funds the borrower has drawn
+    pub principal: Balance,
+    /// Interest charged on the loan
+    pub interest: Balance,
+    /// Fees charged on the loan
+    pub fees: Balance,
+    /// Penalties charged on the loan
+    pub penalties: Balance,
+}
+
+impl Debt {
+    /// Returns a debt that only consists of principal
+    pub fn from_principal(principal: Balance) -> Self {
+        Self {
+            principal,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the part of the debt the payment component stands for
+    pub fn part(&self, component: PaymentComponent) -> Balance {
+        match component {
+            PaymentComponent::Fees => self.fees,
+            PaymentComponent::Penalties => self.penalties,
+            PaymentComponent::Interest => self.interest,
+            PaymentComponent::Principal => self.principal,
+        }
+    }
+
+    fn part_mut(&mut self, component: PaymentComponent) -> &mut Balance {
+        match component {
+            PaymentComponent::Fees => &mut self.fees,
+            PaymentComponent::Penalties => &mut self.penalties,
+            PaymentComponent::Interest => &mut self.interest,
+            PaymentComponent::Principal => &mut self.principal,
+        }
+    }
+
+    /// Adds an amount to the part of the debt the payment component stands for
+    pub fn add(&mut self, component: PaymentComponent, amount: Balance) -> Result<(), LoanError> {
+        let part = self.part_mut(component);
+        *part = part.checked_add(amount).ok_or(LoanError::Overflow)?;
+        Ok(())
+    }
+
+    /// Pays off the parts of the debt in the order of the waterfall
+    /// Returns the amount paid to every part and what is left of the payment
+    pub fn pay(&mut self, amount: Balance, waterfall: &[PaymentComponent]) -> (Debt, Balance) {
+        let mut paid = Debt::default();
+        let mut left = amount;
+        for &component in waterfall {
+            let part = self.part_mut(component);
+            let payment = left.min(*part);
+            *part -= payment;
+            *paid.part_mut(component) = payment;
+            left -= payment;
+        }
+        (paid, left)
+    }
+}
+
+/// Part of the debt that a repayment is applied to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub enum PaymentComponent {
+    /// The fees charged on the loan
+    Fees,
+    /// The penalties charged on the loan
+    Penalties,
+    /// The interest charged on the loan
+    Interest,
+    /// The funds the borrower has drawn
+    Principal,
+}
+
+/// Order in which repayments are applied to the debt unless the admin sets another one
+pub const DEFAULT_PAYMENT_WATERFALL: [PaymentComponent; 4] = [
+    PaymentComponent::Fees,
+    PaymentComponent::Penalties,
+    PaymentComponent::Interest,
+    PaymentComponent::Principal,
+];
+
+/// Kind of a charge that is added to the debt of a loan
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum Charge {
+    /// A fee for a service around the loan
+    Fee,
+    /// A penalty for a breach of the terms, like a late payment
+    Penalty,
+}
+
+impl From<Charge> for PaymentComponent {
+    fn from(charge: Charge) -> Self {
+        match charge {
+            Charge::Fee => PaymentComponent::Fees,
+            Charge::Penalty => PaymentComponent::Penalties,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub enum LoanAsset {
+    /// The native currency of the chain, transferred as value of the calls
+    Native,
+    /// Tokens of a PSP22 contract, the payer has to approve the loan contract to transfer them
+    Psp22(AccountId),
+    /// An asset of the assets pallet, the payer has to approve the loan contract to transfer it
+    PalletAsset(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub enum RepaymentType {
+    /// Every installment pays back an equal part of the principal and the interest on the rest
+    Amortizing,
+    /// Every installment pays the interest, the principal is paid back with the last installment
+    InterestOnly,
+    /// The principal and all interest are paid back in a single installment at maturity
+    Balloon,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct LoanTerms {
+    /// Annual interest rate of the loan in basis points
+    pub interest_rate: u32,
+    /// Timestamp when the loan has to be paid back in full
+    pub maturity: Timestamp,
+    /// Time between two installments in milliseconds, ignored for balloon loans
+    pub payment_frequency: Timestamp,
+    /// How the loan is paid back over its term
+    pub repayment_type: RepaymentType,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub enum InstallmentStatus {
+    /// The installment isn't due yet and hasn't been paid in full
+    Pending,
+    /// The installment has been paid in full until its due date
+    Paid,
+    /// The installment has been paid in full after its due date
+    Late,
+    /// The due date of the installment has passed without it being paid in full
+    Missed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub struct Installment {
+    /// Timestamp until the installment has to be paid
+    pub due_date: Timestamp,
+    /// Part of the principal that is paid back with the installment
+    pub principal: Balance,
+    /// Interest that is paid with the installment
+    pub interest: Balance,
+    /// Amount that has been paid for the installment so far
+    pub paid: Balance,
+    /// Payment status of the installment
+    pub status: InstallmentStatus,
+}
+
+impl Installment {
+    /// Returns the amount that still has to be paid for the installment
+    pub fn amount_due(&self) -> Balance {
+        self.principal
+            .saturating_add(self.interest)
+            .saturating_sub(self.paid)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub enum LoanStatus {
+    /// The loan has been created but nothing has been borrowed yet
+    Pending,
+    /// The borrower has borrowed funds of the loan
+    Active,
+    /// The borrower is behind with the repayments
+    Delinquent,
+    /// The borrower failed to pay back the loan
+    Defaulted,
+    /// The loan has been fully paid back and the collateral given back to the borrower
+    Repaid,
+    /// The loan has been settled and doesn't accept any further action
+    Closed,
+}
+
+impl LoanStatus {
+    /// Returns whether a loan in this status may move to the next status
+    pub fn can_transition_to(&self, next: LoanStatus) -> bool {
+        use LoanStatus::*;
+        matches!(
+            (self, next),
+            (Pending, Active)
+                | (Pending, Closed)
+                | (Active, Delinquent)
+                | (Active, Defaulted)
+                | (Active, Repaid)
+                | (Delinquent, Active)
+                | (Delinquent, Defaulted)
+                | (Delinquent, Repaid)
+                | (Defaulted, Repaid)
+                | (Defaulted, Closed)
+                | (Repaid, Closed)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub struct RuntimeCallIndices {
+    /// Index of the community-loan-pool pallet in the runtime
+    pub community_loan_pool: u8,
+    /// Index of the call that deletes a loan in the pallet
+    pub delete_loan: u8,
+    /// Index of the call that reports a repayment to the pallet
+    pub update_loan: u8,
+    /// Index of the call that reports a liquidation to the pallet
+    pub liquidate_loan: u8,
+    /// Index of the assets pallet in the runtime
+    pub assets: u8,
+    /// Index of the call that transfers an asset of the caller in the assets pallet
+    pub asset_transfer: u8,
+    /// Index of the call that transfers an asset the caller has been approved for
+    pub asset_transfer_approved: u8,
+}
+
+impl RuntimeCallIndices {
+    /// Returns whether the two pallets and every call of a pallet have their own index
+    pub fn is_valid(&self) -> bool {
+        self.community_loan_pool != self.assets
+            && self.delete_loan != self.update_loan
+            && self.delete_loan != self.liquidate_loan
+            && self.update_loan != self.liquidate_loan
+            && self.asset_transfer != self.asset_transfer_approved
+    }
+}
+
+impl Default for RuntimeCallIndices {
+    fn default() -> Self {
+        RuntimeCallIndices {
+            community_loan_pool: 7,
+            delete_loan: 3,
+            update_loan: 4,
+            liquidate_loan: 5,
+            assets: 8,
+            asset_transfer: 8,
+            asset_transfer_approved: 25,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub enum PauseScope {
+    /// Every loan of the contract
+    All,
+    /// A single loan
+    Loan(u32),
+    /// The loans backed by the nfts of a collection
+    Collection(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub enum CollateralRecipient {
+    /// The lender takes over the collateral of a liquidated loan
+    Lender,
+    /// The community-loan-pool takes over the collateral of a liquidated loan
+    CommunityLoanPool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub struct LiquidationConfig {
+    /// Time after the due date of an unpaid installment until the loan can be liquidated
+    pub grace_period: Timestamp,
+    /// Loan to value ratio in basis points above which the loan can be liquidated
+    pub max_ltv: u32,
+    /// Who takes over the collateral of a liquidated loan
+    pub collateral_recipient: CollateralRecipient,
+    /// Share of the recovered debt in basis points that goes to the lender,
+    /// the rest goes to the community-loan-pool
+    pub lender_share: u32,
+}
+
+impl Default for LiquidationConfig {
+    fn default() -> Self {
+        LiquidationConfig {
+            grace_period: 30 * 24 * 60 * 60 * 1000,
+            max_ltv: BASIS_POINTS,
+            collateral_recipient: CollateralRecipient::Lender,
+            lender_share: BASIS_POINTS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct LoanSummary {
+    /// Id of the loan
+    pub loan_id: Id,
+    /// AccountId of the lender
+    pub lender: AccountId,
+    /// AccountId of the borrower
+    pub borrower: AccountId,
+    /// Collection ID of the nft
+    pub collection_id: u32,
+    /// Item ID of the nft
+    pub item_id: u32,
+    /// Asset the loan is in
+    pub asset: LoanAsset,
+    /// Available amount of funds for the borrower to borrow
+    pub available_amount: Balance,
+    /// Amount the borrower owes including the accrued interest
+    pub borrowed_amount: Balance,
+    /// Current status of the loan
+    pub status: LoanStatus,
+    /// Timestamp when the loan has to be paid back in full
+    pub maturity: Timestamp,
+}
+
+impl LoanInfo {
+    /// Returns the summary of the loan that the index queries return
+    pub fn summary(&self, loan_id: Id) -> LoanSummary {
+        LoanSummary {
+            loan_id,
+            lender: self.lender,
+            borrower: self.borrower,
+            collection_id: self.collection_id,
+            item_id: self.item_id,
+            asset: self.asset,
+            available_amount: self.available_amount,
+            borrowed_amount: self.borrowed_amount,
+            status: self.status,
+            maturity: self.maturity,
+        }
+    }
+}
+
+/// Aggregates over the loans in one asset that haven't been deleted, as stored when they were
+/// last updated, the amounts are in units of the asset
+#[derive(Debug, Default, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub struct PortfolioStats {
+    /// Funds of the lenders that the borrowers can still borrow
+    pub total_committed: Balance,
+    /// Amount the borrowers owe including the interest charged
+    pub total_outstanding: Balance,
+    /// Interest charged on all loans, including deleted ones
+    pub total_interest: Balance,
+    /// Amount paid back on all loans, including deleted ones
+    pub total_repaid: Balance,
+    /// Number of pending loans
+    pub pending_loans: u32,
+    /// Number of active loans
+    pub active_loans: u32,
+    /// Number of delinquent loans
+    pub delinquent_loans: u32,
+    /// Number of defaulted loans
+    pub defaulted_loans: u32,
+    /// Number of repaid loans that haven't been deleted
+    pub repaid_loans: u32,
+    /// Number of liquidated loans
+    pub closed_loans: u32,
+}
+
+impl PortfolioStats {
+    /// Returns the number of loans in the status
+    pub fn loans_in(&self, status: LoanStatus) -> u32 {
+        match status {
+            LoanStatus::Pending => self.pending_loans,
+            LoanStatus::Active => self.active_loans,
+            LoanStatus::Delinquent => self.delinquent_loans,
+            LoanStatus::Defaulted => self.defaulted_loans,
+            LoanStatus::Repaid => self.repaid_loans,
+            LoanStatus::Closed => self.closed_loans,
+        }
+    }
+
+    fn loans_in_mut(&mut self, status: LoanStatus) -> &mut u32 {
+        match status {
+            LoanStatus::Pending => &mut self.pending_loans,
+            LoanStatus::Active => &mut self.active_loans,
+            LoanStatus::Delinquent => &mut self.delinquent_loans,
+            LoanStatus::Defaulted => &mut self.defaulted_loans,
+            LoanStatus::Repaid => &mut self.repaid_loans,
+            LoanStatus::Closed => &mut self.closed_loans,
+        }
+    }
+
+    /// Adds the amounts and status of a loan to the aggregates
+    pub fn add_loan(&mut self, loan_info: &LoanInfo) -> Result<(), LoanError> {
+        self.total_committed = self
+            .total_committed
+            .checked_add(loan_info.available_amount)
+            .ok_or(LoanError::Overflow)?;
+        self.total_outstanding = self
+            .total_outstanding
+            .checked_add(loan_info.borrowed_amount)
+            .ok_or(LoanError::Overflow)?;
+        let loans = self.loans_in_mut(loan_info.status);
+        *loans = loans.checked_add(1).ok_or(LoanError::Overflow)?;
+        Ok(())
+    }
+
+    /// Removes the amounts and status of a loan that has been added before from the aggregates
+    pub fn remove_loan(&mut self, loan_info: &LoanInfo) -> Result<(), LoanError> {
+        self.total_committed = self
+            .total_committed
+            .checked_sub(loan_info.available_amount)
+            .ok_or(LoanError::Overflow)?;
+        self.total_outstanding = self
+            .total_outstanding
+            .checked_sub(loan_info.borrowed_amount)
+            .ok_or(LoanError::Overflow)?;
+        let loans = self.loans_in_mut(loan_info.status);
+        *loans = loans.checked_sub(1).ok_or(LoanError::Overflow)?;
+        Ok(())
+    }
+}
+
+/// Funds the contract owes in the native currency compared to its balance, in the smallest unit
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+pub struct SolvencyReport {
+    /// Balance of the contract
+    pub balance: Balance,
+    /// Funds of the native loans that the borrowers can still borrow
+    pub committed: Balance,
+    /// Value credited to the callers
+    pub credit: Balance,
+    /// Amount by which the balance falls short of the committed funds and credits
+    pub deficit: Balance,
+}
+
+impl SolvencyReport {
+    /// Returns whether the balance covers the committed funds and credits
+    pub fn is_solvent(&self) -> bool {
+        self.deficit == 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub struct Liquidation {
+    /// Timestamp when the loan has been liquidated
+    pub timestamp: Timestamp,
+    /// Account that took over the collateral
+    pub recipient: AccountId,
+    /// Outstanding debt of the loan when it has been liquidated
+    pub debt: Balance,
+    /// Part of the debt that isn't covered, estimated from the collateral price
+    /// until proceeds have been recovered
+    pub shortfall: Balance,
+    /// Proceeds that have been recovered from the collateral so far
+    pub recovered: Balance,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub struct StatusChange {
+    /// Status the loan moved to
+    pub status: LoanStatus,
+    /// Timestamp when the loan moved to the status
+    pub timestamp: Timestamp,
+}
+
 #[openbrush::wrapper]
 pub type LoanRef = dyn Loan;
 
 #[openbrush::trait_definition]
 pub trait Loan {
-    // This function will create a new loan
+    // This function will create a new loan and takes the nft of the borrower into escrow
+    // The collateral price is deprecated, it is only kept on the loan for reference while the
+    // LTV checks use the valuations of the oracle
     #[ink(message, payable)]
+    #[allow(clippy::too_many_arguments)]
     fn create_loan(
         &mut self,
         lender: AccountId,
@@ -56,22 +663,49 @@ pub trait Loan {
         item_id: u32,
         collateral_price: Balance,
         available_amount: Balance,
+        terms: LoanTerms,
     ) -> Result<(), LoanError>;
 
-    // This function will delete the loan and burns the nft
+    // This function will create a new loan in a PSP22 token or an asset of the assets pallet
+    // The available amount is transferred from the caller, who has to approve the contract for it,
+    // and the collateral price is deprecated like the one of create_loan
+    #[ink(message, payable)]
+    #[allow(clippy::too_many_arguments)]
+    fn create_asset_loan(
+        &mut self,
+        lender: AccountId,
+        borrower: AccountId,
+        collection_id: u32,
+        item_id: u32,
+        collateral_price: Balance,
+        available_amount: Balance,
+        terms: LoanTerms,
+        asset: LoanAsset,
+    ) -> Result<(), LoanError>;
+
+    // This function will delete the loan and gives the nft back to the borrower
     // It call the community-loan-pallet and send the remaining available amount back to the pallet
+    // Only the lender can delete the loan, and the lender needs the LOAN_ORIGINATOR role
     #[ink(message)]
     fn delete_loan(&mut self, loan_id: Id) -> Result<(), LoanError>;
 
     // This function will update the loan
+    // Only the lender can top up the loan, and the lender needs the LOAN_ORIGINATOR role
     #[ink(message, payable)]
     fn update_loan(&mut self, loan_id: Id, new_borrow_amount: Balance) -> Result<(), LoanError>;
 
-    #[ink(message, payable)]
-    fn charge_apy(&mut self, loan_id: Id, amount: Balance) -> Result<(), LoanError>;
+    // This function charges the interest accrued since the last accrual to the loan
+    #[ink(message)]
+    fn charge_apy(&mut self, loan_id: Id) -> Result<(), LoanError>;
+
+    // This function adds a fee or a penalty to the debt of the loan
+    #[ink(message)]
+    fn add_charge(&mut self, loan_id: Id, charge: Charge, amount: Balance)
+        -> Result<(), LoanError>;
 
-    // This function is for the lender to repay the loan
+    // This function is for the borrower to repay the loan, other accounts can't repay it
     // The repaying amount is directly send to the pallet
+    // The nft is given back to the borrower once the loan is fully repaid
     #[ink(message, payable)]
     fn repay(&mut self, loan_id: Id, repay_amount: Balance) -> Result<(), LoanError>;
 
@@ -79,8 +713,105 @@ pub trait Loan {
     #[ink(message)]
     fn withdraw_funds(&mut self, loan_id: Id, amount: Balance) -> Result<(), LoanError>;
 
+    // This function liquidates a loan that is defaulted, past the grace period of an unpaid
+    // installment or above the maximum loan to value ratio
+    // The nft is given to the lender or the community-loan-pool and the shortfall is recorded
+    #[ink(message)]
+    fn liquidate(&mut self, loan_id: Id) -> Result<(), LoanError>;
+
+    // This function splits the proceeds recovered from the collateral of a liquidated loan
+    // The recovered debt is shared between the lender and the community-loan-pool,
+    // anything above the debt is given to the borrower
+    #[ink(message, payable)]
+    fn distribute_recovery(&mut self, loan_id: Id, amount: Balance) -> Result<(), LoanError>;
+
+    // This function returns the liquidation of the loan if it has been liquidated
+    #[ink(message)]
+    fn get_liquidation(&self, loan_id: Id) -> Option<Liquidation>;
+
+    // This function lets the admin mark a loan as delinquent or defaulted, or as active again
+    #[ink(message)]
+    fn update_loan_status(&mut self, loan_id: Id, status: LoanStatus) -> Result<(), LoanError>;
+
+    // This function returns every status the loan went through, starting with its creation
+    #[ink(message)]
+    fn get_status_history(&self, loan_id: Id) -> Vec<StatusChange>;
+
+    // This function returns the installments of the loan with their payment status
+    #[ink(message)]
+    fn get_schedule(&self, loan_id: Id) -> Vec<Installment>;
+
+    // This function returns the due date of the next unpaid installment
+    // and the amount that has to be paid until then, including overdue installments
+    #[ink(message)]
+    fn next_amount_due(&self, loan_id: Id) -> Option<(Timestamp, Balance)>;
+
+    // This function returns the loan to value ratio of the loan in basis points,
+    // based on the debt including the accrued interest and the latest valuation of the nft
+    #[ink(message)]
+    fn current_ltv(&self, loan_id: Id) -> Result<u32, LoanError>;
+
+    // This function returns the loan including the interest accrued until now
+    // It traps if the loan doesn't exist, callers should prefer `try_get_loan_info`
     #[ink(message)]
     fn get_loan_info(&self, loan_id: Id) -> LoanInfo;
+
+    // This function returns the version of the query messages below
+    #[ink(message)]
+    fn query_version(&self) -> u32;
+
+    // This function returns the loan including the interest accrued until now,
+    // or `None` if the loan doesn't exist or its interest can't be accrued
+    #[ink(message)]
+    fn try_get_loan_info(&self, loan_id: Id) -> Option<LoanInfo>;
+
+    // This function returns whether a loan exists for the loan_id
+    #[ink(message)]
+    fn loan_exists(&self, loan_id: Id) -> bool;
+
+    // This function returns the ids of up to `limit` loans of the borrower,
+    // starting at position `offset` of the loans that haven't been deleted
+    #[ink(message)]
+    fn loans_of_borrower(&self, borrower: AccountId, offset: u32, limit: u32) -> Vec<Id>;
+
+    // This function returns the summaries of up to `limit` loans of the borrower,
+    // starting at position `offset` of the loans that haven't been deleted
+    #[ink(message)]
+    fn loans_by_borrower(&self, borrower: AccountId, offset: u32, limit: u32) -> Vec<LoanSummary>;
+
+    // This function returns the summaries of up to `limit` loans of the lender,
+    // starting at position `offset` of the loans that haven't been deleted
+    #[ink(message)]
+    fn loans_by_lender(&self, lender: AccountId, offset: u32, limit: u32) -> Vec<LoanSummary>;
+
+    // This function returns the summaries of up to `limit` loans backed by nfts of the collection,
+    // starting at position `offset` of the loans that haven't been deleted
+    #[ink(message)]
+    fn loans_by_collection(&self, collection_id: u32, offset: u32, limit: u32) -> Vec<LoanSummary>;
+
+    // This function returns the aggregated amounts and status counts of the loans in the asset,
+    // the amounts are in units of the asset
+    #[ink(message)]
+    fn portfolio_stats(&self, asset: LoanAsset) -> PortfolioStats;
+
+    // This function lets the lender hand a live loan over to a new lender with the LOAN_ORIGINATOR
+    // role, who can top up or delete the loan from then on, the repayments still go to the
+    // community-loan-pool
+    #[ink(message)]
+    fn transfer_loan(&mut self, loan_id: Id, new_lender: AccountId) -> Result<(), LoanError>;
+
+    // This function returns the number of decimals of a whole unit of the loan amounts
+    #[ink(message)]
+    fn decimals(&self) -> u8;
+
+    // This function returns the value an account transferred above the required amounts,
+    // in the balance of the chain
+    #[ink(message)]
+    fn get_credit(&self, account: AccountId) -> Balance;
+
+    // This function lets the caller withdraw its whole credit
+    #[ink(message)]
+    fn withdraw_credit(&mut self) -> Result<(), LoanError>;
 }
 
 #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
@@ -108,6 +839,90 @@ pub enum LoanError {
     NotEnoughFundsProvided,
     /// Error if the runtime call failed
     CallRuntimeFailed,
+    /// This error will be thrown if a value from the environment couldn't be decoded
+    DecodeFailed,
+    /// This error will be thrown if a called contract trapped
+    CalleeTrapped,
+    /// This error will be thrown if a called contract reverted
+    CalleeReverted,
+    /// This error will be thrown if a queried storage entry is missing
+    KeyNotFound,
+    /// This error will be thrown if a transfer would leave less than the minimum balance
+    BelowSubsistenceThreshold,
+    /// This error will be thrown if a transfer of funds failed
+    TransferFailed,
+    /// This error will be thrown if the endowment of an instantiated contract is too low
+    EndowmentTooLow,
+    /// This error will be thrown if no code exists for a code hash
+    CodeNotFound,
+    /// This error will be thrown if a called account isn't a contract
+    NotCallable,
+    /// This error will be thrown if debug messages are disabled
+    LoggingDisabled,
+    /// This error will be thrown if the recovery of an ECDSA public key failed
+    EcdsaRecoveryFailed,
+    /// This error will be thrown for any other error of the environment
+    UnknownEnvError,
+    /// This error will be thrown if the caller hasn't been granted the role for the function
+    MissingRole(RoleType),
+    /// This error will be thrown if no PSP34 contract has been registered for the collection
+    UnknownCollection,
+    /// This error will be thrown if a PSP34 contract is already registered for the collection
+    CollectionAlreadyRegistered,
+    /// This error will be thrown if the borrower doesn't own the collateral nft
+    NotTheCollateralOwner,
+    /// This error will be thrown if the PSP34 contract failed to transfer the collateral nft
+    CollateralTransferFailed,
+    /// This error will be thrown if the function can't be called in the current status of the loan
+    InvalidLoanStatus(LoanStatus),
+    /// This error will be thrown if the loan can't move from its current status to the new one
+    InvalidStatusTransition,
+    /// This error will be thrown if the maturity or the payment frequency of the loan is invalid
+    InvalidLoanTerms,
+    /// This error will be thrown if the maturity of the loan has passed
+    LoanMatured,
+    /// This error will be thrown if the loan is neither past its grace period nor above the maximum LTV
+    NotLiquidatable,
+    /// This error will be thrown if the loan hasn't been liquidated
+    NotLiquidated,
+    /// This error will be thrown if the loan to value ratio or the lender share is out of range
+    InvalidLiquidationConfig,
+    /// This error will be thrown if the oracle reports a price of zero or one it can't report
+    InvalidValuation,
+    /// This error will be thrown if the valuation of the collateral or the price of the asset is stale
+    StaleValuation,
+    /// This error will be thrown if the loan would exceed the maximum loan to value ratio
+    LtvExceeded,
+    /// This error will be thrown if a maximum loan to value ratio is zero or above 100%
+    InvalidLtv,
+    /// This error will be thrown if a calculation exceeds the range of its type
+    Overflow,
+    /// This error will be thrown if the repay amount is higher than the outstanding debt
+    RepayExceedsDebt,
+    /// This error will be thrown if the caller has no credit to withdraw
+    NoCredit,
+    /// This error will be thrown if the two pallets or two calls of a pallet share an index
+    InvalidCallIndices,
+    /// This error will be thrown if the function moves value while it is paused for the scope
+    Paused(PauseScope),
+    /// This error will be thrown if the scope to unpause isn't paused
+    NotPaused,
+    /// This error will be thrown if the storage already has the current version
+    NothingToMigrate,
+    /// This error will be thrown if the PSP22 contract or the assets pallet rejected a transfer
+    AssetTransferFailed,
+    /// This error will be thrown if the loan is transferred to its current lender
+    SameLender,
+    /// This error will be thrown if the payment waterfall doesn't name every part of the debt once
+    InvalidWaterfall,
+    /// This error will be thrown if a charge of zero is added to a loan
+    ChargeMustBeHigherThanZero,
+    /// This error will be thrown if the caller isn't the owner of the contract
+    CallerIsNotOwner,
+    /// This error will be thrown until the root of an older storage version has been migrated
+    MigrationPending,
+    /// This error will be thrown if a loan is transferred to an account without the LOAN_ORIGINATOR role
+    NewLenderNotOriginator,
 }
 
 use ink::env::Error as EnvError;
@@ -116,7 +931,19 @@ impl From<EnvError> for LoanError {
     fn from(e: EnvError) -> Self {
         match e {
             EnvError::CallRuntimeFailed => LoanError::CallRuntimeFailed,
-            _ => panic!("Unexpected error from `pallet-contracts`."),
+            EnvError::Decode(_) => LoanError::DecodeFailed,
+            EnvError::CalleeTrapped => LoanError::CalleeTrapped,
+            EnvError::CalleeReverted => LoanError::CalleeReverted,
+            EnvError::KeyNotFound => LoanError::KeyNotFound,
+            EnvError::_BelowSubsistenceThreshold => LoanError::BelowSubsistenceThreshold,
+            EnvError::TransferFailed => LoanError::TransferFailed,
+            EnvError::_EndowmentTooLow => LoanError::EndowmentTooLow,
+            EnvError::CodeNotFound => LoanError::CodeNotFound,
+            EnvError::NotCallable => LoanError::NotCallable,
+            EnvError::LoggingDisabled => LoanError::LoggingDisabled,
+            EnvError::EcdsaRecoveryFailed => LoanError::EcdsaRecoveryFailed,
+            // Covers `Unknown` and the errors of the off-chain environment
+            _ => LoanError::UnknownEnvError,
         }
     }
 }
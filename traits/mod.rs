@@ -1 +1,2 @@
 pub mod loan;
+pub mod oracle;
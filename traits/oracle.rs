@@ -0,0 +1,90 @@
+use crate::traits::loan::{LoanAsset, LoanError};
+use ink::prelude::vec::Vec;
+use openbrush::{
+    contracts::access_control::RoleType,
+    traits::{Balance, Timestamp},
+};
+
+/// Role that is allowed to refresh the valuations of the collateral nfts
+pub const ORACLE: RoleType = ink::selector_id!("ORACLE");
+
+/// Age after which a valuation is considered stale unless the admin sets another one
+pub const DEFAULT_MAX_VALUATION_AGE: Timestamp = 7 * 24 * 60 * 60 * 1000;
+
+/// Highest number of valuations that are returned by a single history query
+pub const MAX_VALUATION_PAGE: u32 = 100;
+
+/// Number of decimals the prices of the loan assets are given with
+pub const ASSET_PRICE_DECIMALS: u8 = 9;
+
+#[cfg(feature = "std")]
+use ink::storage::traits::StorageLayout;
+
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub struct Valuation {
+    /// Price of the nft in whole units
+    pub price: Balance,
+    /// Timestamp when the nft has been valued
+    pub timestamp: Timestamp,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, scale::Encode, scale::Decode)]
+#[cfg_attr(feature = "std", derive(StorageLayout, scale_info::TypeInfo))]
+pub struct AssetPrice {
+    /// Number of decimals between a whole unit of the asset and the amounts of its loans
+    pub decimals: u8,
+    /// Value of a whole unit of the asset in the whole units of the valuations,
+    /// scaled by `10^ASSET_PRICE_DECIMALS`
+    pub price: Balance,
+    /// Timestamp when the asset has been priced
+    pub timestamp: Timestamp,
+}
+
+#[openbrush::wrapper]
+pub type OracleRef = dyn Oracle;
+
+#[openbrush::trait_definition]
+pub trait Oracle {
+    // This function lets the oracle refresh the valuation of an nft of a registered collection
+    #[ink(message)]
+    fn update_valuation(
+        &mut self,
+        collection_id: u32,
+        item_id: u32,
+        price: Balance,
+    ) -> Result<(), LoanError>;
+
+    // This function returns the latest valuation of the nft, even if it is stale
+    #[ink(message)]
+    fn get_valuation(&self, collection_id: u32, item_id: u32) -> Option<Valuation>;
+
+    // This function returns the valuations of the nft from the oldest to the newest,
+    // starting at the index `from` and at most `limit` of them
+    #[ink(message)]
+    fn get_valuation_history(
+        &self,
+        collection_id: u32,
+        item_id: u32,
+        from: u32,
+        limit: u32,
+    ) -> Vec<Valuation>;
+
+    // This function lets the oracle price a loan asset other than the native currency,
+    // the collateral of loans in the asset is only valued with a price that isn't stale
+    #[ink(message)]
+    fn update_asset_price(
+        &mut self,
+        asset: LoanAsset,
+        decimals: u8,
+        price: Balance,
+    ) -> Result<(), LoanError>;
+
+    // This function returns the latest price of the asset, even if it is stale
+    #[ink(message)]
+    fn get_asset_price(&self, asset: LoanAsset) -> Option<AssetPrice>;
+
+    // This function returns how old a valuation may be before it is considered stale
+    #[ink(message)]
+    fn max_valuation_age(&self) -> Timestamp;
+}